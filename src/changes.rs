@@ -0,0 +1,477 @@
+use crate::collector::Inventory;
+use crate::config;
+use crate::hardware::NodeHardware;
+use crate::software::NodeSoftware;
+use crate::system::NodeSystem;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const SNAPSHOT_FILE: &str = "last_inventory.json";
+
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    GpuAdded,
+    GpuRemoved,
+    GpuChanged,
+    MemoryChanged,
+    MemoryModuleAdded,
+    MemoryModuleRemoved,
+    MemoryModuleChanged,
+    CpuCoresChanged,
+    DiskAdded,
+    DiskRemoved,
+    DiskChanged,
+    SoftwareChanged,
+    KernelChanged,
+    OsChanged,
+}
+
+/// A difference between the previous and the current inventory.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// What changed, e.g. the PCI address of a GPU or the name of a version.
+    pub subject: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// The parts of an inventory that are compared between runs. A section is
+/// `None` until its collector succeeded once.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct InventorySnapshot {
+    hardware: Option<HardwareSnapshot>,
+    software: Option<SoftwareSnapshot>,
+    system: Option<SystemSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct HardwareSnapshot {
+    /// GPU descriptions keyed by PCI address.
+    gpus: BTreeMap<String, String>,
+    memory_gb: u64,
+    cpu_cores: u64,
+    /// DIMM descriptions keyed by slot.
+    memory_modules: BTreeMap<String, String>,
+    /// Disk descriptions keyed by device name.
+    disks: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SoftwareSnapshot {
+    versions: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SystemSnapshot {
+    kernel_release: String,
+    os: String,
+}
+
+impl InventorySnapshot {
+    fn from_inventory(inventory: &Inventory) -> Self {
+        InventorySnapshot {
+            hardware: inventory
+                .hardware
+                .as_ref()
+                .map(HardwareSnapshot::from_hardware),
+            software: inventory
+                .software
+                .as_ref()
+                .map(SoftwareSnapshot::from_software),
+            system: inventory.system.as_ref().map(SystemSnapshot::from_system),
+        }
+    }
+
+    /// Fills the sections that were not collected in this run from `previous`.
+    fn or_previous(self, previous: &InventorySnapshot) -> Self {
+        InventorySnapshot {
+            hardware: self.hardware.or_else(|| previous.hardware.clone()),
+            software: self.software.or_else(|| previous.software.clone()),
+            system: self.system.or_else(|| previous.system.clone()),
+        }
+    }
+}
+
+impl HardwareSnapshot {
+    fn from_hardware(hardware: &NodeHardware) -> Self {
+        HardwareSnapshot {
+            gpus: hardware
+                .gpus
+                .iter()
+                .map(|gpu| {
+                    (
+                        gpu.pci_address.to_owned(),
+                        format!("{} {} ({} GB)", gpu.vendor, gpu.gpu_type, gpu.vram),
+                    )
+                })
+                .collect(),
+            memory_gb: hardware.memory_gb,
+            cpu_cores: hardware.cpu_cores,
+            memory_modules: hardware
+                .dmi
+                .memory_modules
+                .iter()
+                .enumerate()
+                .map(|(idx, module)| {
+                    let slot = module
+                        .locator
+                        .to_owned()
+                        .unwrap_or_else(|| format!("module {idx}"));
+                    let description = format!(
+                        "{} MB {} {}",
+                        module.size_mb,
+                        module.memory_type,
+                        module.part_number.as_deref().unwrap_or("unknown part")
+                    );
+                    (slot, description)
+                })
+                .collect(),
+            disks: hardware
+                .storage
+                .disks
+                .iter()
+                .map(|disk| {
+                    let description = format!(
+                        "{} ({} bytes)",
+                        disk.model.as_deref().unwrap_or("unknown model"),
+                        disk.size_bytes
+                    );
+                    (disk.name.to_owned(), description)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl SoftwareSnapshot {
+    fn from_software(software: &NodeSoftware) -> Self {
+        let mut versions = BTreeMap::new();
+        // every parsed version field, without listing them here again
+        if let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(&software.versions) {
+            for (name, value) in fields {
+                if let Some(version) = value.as_str() {
+                    versions.insert(name, version.to_string());
+                }
+            }
+        }
+        for (name, tool) in &software.tools {
+            if let Some(version) = &tool.version {
+                versions.insert(name.to_owned(), version.to_owned());
+            }
+        }
+        SoftwareSnapshot { versions }
+    }
+}
+
+impl SystemSnapshot {
+    fn from_system(system: &NodeSystem) -> Self {
+        SystemSnapshot {
+            kernel_release: system.kernel_release.to_owned(),
+            os: system
+                .os_release
+                .pretty_name
+                .to_owned()
+                .unwrap_or_else(|| system.os.to_owned()),
+        }
+    }
+}
+
+/// Compares the inventory with the baseline of the last reported run.
+/// Sections missing in this run keep their previous state, so a failed
+/// collector is not reported as removed hardware. The baseline only moves
+/// with `commit_snapshot`, so changes are reported until a heartbeat carried
+/// them.
+pub(crate) fn detect_changes(
+    inventory: &Inventory,
+) -> Result<Vec<Change>, Box<dyn std::error::Error>> {
+    let path = config::state_dir_path()?.join(SNAPSHOT_FILE);
+    Ok(detect_changes_at(&path, inventory))
+}
+
+/// Stores the inventory as the new baseline.
+pub(crate) fn commit_snapshot(inventory: &Inventory) -> Result<(), Box<dyn std::error::Error>> {
+    let path = config::state_dir_path()?.join(SNAPSHOT_FILE);
+    commit_snapshot_at(&path, inventory)
+}
+
+fn detect_changes_at(path: &Path, inventory: &Inventory) -> Vec<Change> {
+    let previous = load_snapshot(path);
+    let current = InventorySnapshot::from_inventory(inventory).or_previous(&previous);
+
+    let changes = diff(&previous, &current);
+    for change in &changes {
+        warn!(
+            "Inventory change {:?} of {}: {} -> {}",
+            change.kind,
+            change.subject,
+            change.before.as_deref().unwrap_or("none"),
+            change.after.as_deref().unwrap_or("none")
+        );
+    }
+    changes
+}
+
+fn commit_snapshot_at(
+    path: &Path,
+    inventory: &Inventory,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let current = InventorySnapshot::from_inventory(inventory).or_previous(&load_snapshot(path));
+    fs::write(path, serde_json::to_string(&current)?)?;
+    Ok(())
+}
+
+fn load_snapshot(path: &Path) -> InventorySnapshot {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str::<InventorySnapshot>(&content).unwrap_or_else(|e| {
+            warn!(
+                "Ignoring unreadable inventory snapshot {}: {e}",
+                path.display()
+            );
+            InventorySnapshot::default()
+        }),
+        Err(_) => {
+            info!("No previous inventory snapshot found");
+            InventorySnapshot::default()
+        }
+    }
+}
+
+fn diff(previous: &InventorySnapshot, current: &InventorySnapshot) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    if let (Some(before), Some(after)) = (&previous.hardware, &current.hardware) {
+        diff_maps(
+            &before.gpus,
+            &after.gpus,
+            [
+                ChangeKind::GpuAdded,
+                ChangeKind::GpuRemoved,
+                ChangeKind::GpuChanged,
+            ],
+            &mut changes,
+        );
+        diff_value(
+            ChangeKind::MemoryChanged,
+            "memory_gb",
+            before.memory_gb,
+            after.memory_gb,
+            &mut changes,
+        );
+        diff_value(
+            ChangeKind::CpuCoresChanged,
+            "cpu_cores",
+            before.cpu_cores,
+            after.cpu_cores,
+            &mut changes,
+        );
+        diff_maps(
+            &before.memory_modules,
+            &after.memory_modules,
+            [
+                ChangeKind::MemoryModuleAdded,
+                ChangeKind::MemoryModuleRemoved,
+                ChangeKind::MemoryModuleChanged,
+            ],
+            &mut changes,
+        );
+        diff_maps(
+            &before.disks,
+            &after.disks,
+            [
+                ChangeKind::DiskAdded,
+                ChangeKind::DiskRemoved,
+                ChangeKind::DiskChanged,
+            ],
+            &mut changes,
+        );
+    }
+
+    if let (Some(before), Some(after)) = (&previous.software, &current.software) {
+        diff_maps(
+            &before.versions,
+            &after.versions,
+            [ChangeKind::SoftwareChanged; 3],
+            &mut changes,
+        );
+    }
+
+    if let (Some(before), Some(after)) = (&previous.system, &current.system) {
+        diff_value(
+            ChangeKind::KernelChanged,
+            "kernel_release",
+            &before.kernel_release,
+            &after.kernel_release,
+            &mut changes,
+        );
+        diff_value(
+            ChangeKind::OsChanged,
+            "os",
+            &before.os,
+            &after.os,
+            &mut changes,
+        );
+    }
+
+    changes
+}
+
+fn diff_value<T: PartialEq + ToString>(
+    kind: ChangeKind,
+    subject: &str,
+    before: T,
+    after: T,
+    changes: &mut Vec<Change>,
+) {
+    if before != after {
+        changes.push(Change {
+            kind,
+            subject: subject.to_string(),
+            before: Some(before.to_string()),
+            after: Some(after.to_string()),
+        });
+    }
+}
+
+/// Reports added, removed and changed entries with the kinds in this order.
+fn diff_maps(
+    before: &BTreeMap<String, String>,
+    after: &BTreeMap<String, String>,
+    [added, removed, changed]: [ChangeKind; 3],
+    changes: &mut Vec<Change>,
+) {
+    for (key, old) in before {
+        match after.get(key) {
+            None => changes.push(Change {
+                kind: removed,
+                subject: key.to_owned(),
+                before: Some(old.to_owned()),
+                after: None,
+            }),
+            Some(new) if new != old => changes.push(Change {
+                kind: changed,
+                subject: key.to_owned(),
+                before: Some(old.to_owned()),
+                after: Some(new.to_owned()),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (key, new) in after {
+        if !before.contains_key(key) {
+            changes.push(Change {
+                kind: added,
+                subject: key.to_owned(),
+                before: None,
+                after: Some(new.to_owned()),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hardware(gpus: &[(&str, &str)], memory_gb: u64) -> HardwareSnapshot {
+        HardwareSnapshot {
+            gpus: gpus
+                .iter()
+                .map(|(address, description)| (address.to_string(), description.to_string()))
+                .collect(),
+            memory_gb,
+            cpu_cores: 64,
+            memory_modules: BTreeMap::from([(
+                String::from("DIMM_A1"),
+                String::from("32768 MB DDR5 M321R4GA3BB6-CQKET"),
+            )]),
+            disks: BTreeMap::new(),
+        }
+    }
+
+    fn snapshot(hardware: HardwareSnapshot, driver: &str, kernel: &str) -> InventorySnapshot {
+        InventorySnapshot {
+            hardware: Some(hardware),
+            software: Some(SoftwareSnapshot {
+                versions: BTreeMap::from([(String::from("nvidia_driver"), driver.to_string())]),
+            }),
+            system: Some(SystemSnapshot {
+                kernel_release: kernel.to_string(),
+                os: String::from("Ubuntu 24.04.1 LTS"),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_diff_reports_changes() {
+        let h100 = "NVIDIA H100 80GB HBM3 (80 GB)";
+        let previous = snapshot(
+            hardware(&[("0000:17:00.0", h100), ("0000:2a:00.0", h100)], 512),
+            "550.54.15",
+            "6.8.0-45-generic",
+        );
+        let mut current_hardware = hardware(&[("0000:17:00.0", h100)], 480);
+        current_hardware.memory_modules.clear();
+        let current = snapshot(current_hardware, "560.35.03", "6.8.0-48-generic");
+
+        let changes = diff(&previous, &current);
+
+        let kinds: Vec<ChangeKind> = changes.iter().map(|change| change.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ChangeKind::GpuRemoved,
+                ChangeKind::MemoryChanged,
+                ChangeKind::MemoryModuleRemoved,
+                ChangeKind::SoftwareChanged,
+                ChangeKind::KernelChanged,
+            ]
+        );
+        assert_eq!(changes[0].subject, "0000:2a:00.0");
+        assert_eq!(changes[3].before.as_deref(), Some("550.54.15"));
+        assert_eq!(changes[3].after.as_deref(), Some("560.35.03"));
+    }
+
+    #[test]
+    fn test_missing_sections_keep_previous_state() {
+        let previous = snapshot(hardware(&[], 512), "550.54.15", "6.8.0-45-generic");
+        let current = InventorySnapshot {
+            hardware: None,
+            ..snapshot(hardware(&[], 512), "550.54.15", "6.8.0-48-generic")
+        }
+        .or_previous(&previous);
+
+        let changes = diff(&previous, &current);
+
+        assert_eq!(current.hardware.unwrap().memory_gb, 512);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].kind, ChangeKind::KernelChanged);
+    }
+
+    #[test]
+    fn test_diff_without_previous_snapshot() {
+        let current = snapshot(hardware(&[], 512), "550.54.15", "6.8.0-45-generic");
+
+        assert!(diff(&InventorySnapshot::default(), &current).is_empty());
+    }
+
+    #[test]
+    fn test_changes_reported_until_committed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(SNAPSHOT_FILE);
+        let mut inventory = Inventory::mock();
+        commit_snapshot_at(&path, &inventory).unwrap();
+
+        inventory.hardware.as_mut().unwrap().memory_gb = 32;
+        assert_eq!(detect_changes_at(&path, &inventory).len(), 1);
+        // a run that did not report the change sees it again
+        assert_eq!(detect_changes_at(&path, &inventory).len(), 1);
+
+        commit_snapshot_at(&path, &inventory).unwrap();
+        assert!(detect_changes_at(&path, &inventory).is_empty());
+    }
+}
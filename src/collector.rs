@@ -0,0 +1,502 @@
+use crate::changes::Change;
+use crate::config::{CollectorSettings, Settings};
+use crate::facts;
+use crate::gpu_metrics::{self, GpuMetrics};
+use crate::hardware::{self, NodeHardware};
+use crate::health::{self, HealthReport};
+use crate::kernel_log::{self, KernelLogReport};
+use crate::metrics::{self, HostMetrics};
+use crate::software::{self, NodeSoftware};
+use crate::system::{self, NodeSystem};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+use sysinfo::System;
+
+/// Names of the built-in collectors in the order they run.
+pub(crate) const BUILTIN_COLLECTORS: &[&str] = &[
+    "hardware",
+    "software",
+    "system",
+    "facts",
+    "gpu_metrics",
+    "metrics",
+    "kernel_log",
+];
+
+/// A source of inventory data. Every collector runs on its own thread, so a
+/// failing, panicking or hanging collector only loses its own section.
+pub(crate) trait Collector: Send + 'static {
+    type Output: Send + 'static;
+
+    fn name(&self) -> &'static str;
+
+    /// Time after which the collection is abandoned unless the settings
+    /// override it.
+    fn timeout(&self) -> Duration;
+
+    fn collect(&self) -> Result<Self::Output, Box<dyn std::error::Error>>;
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CollectorStatus {
+    Ok,
+    Failed,
+    TimedOut,
+    Disabled,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CollectorReport {
+    pub status: CollectorStatus,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Everything collected in one run. A section is `None` if its collector is
+/// disabled or failed; the reason is in `collectors`.
+#[derive(Serialize, Debug)]
+pub struct Inventory {
+    pub hardware: Option<NodeHardware>,
+    pub software: Option<NodeSoftware>,
+    pub system: Option<NodeSystem>,
+    /// Site specific facts from `facts.d`, keyed by the fact name.
+    pub custom: Option<BTreeMap<String, serde_json::Value>>,
+    pub gpu_metrics: Option<Vec<GpuMetrics>>,
+    /// Current host utilization, sampled over a short window.
+    pub metrics: Option<HostMetrics>,
+    /// GPU and hardware faults logged by the kernel since the previous run.
+    pub kernel_log: Option<KernelLogReport>,
+    /// Verdict whether the node is fit to run jobs, derived from the sections above.
+    pub health: Option<HealthReport>,
+    /// Differences to the inventory of the previous run.
+    pub changes: Option<Vec<Change>>,
+    pub collectors: BTreeMap<String, CollectorReport>,
+}
+
+struct HardwareCollector {
+    settings: Settings,
+    sys: Arc<Mutex<System>>,
+}
+
+impl Collector for HardwareCollector {
+    type Output = NodeHardware;
+
+    fn name(&self) -> &'static str {
+        "hardware"
+    }
+
+    fn timeout(&self) -> Duration {
+        // includes fetching the online PCI database and the interconnect probes
+        Duration::from_secs(300)
+    }
+
+    fn collect(&self) -> Result<NodeHardware, Box<dyn std::error::Error>> {
+        hardware::collect_client_hardware(&self.settings, &self.sys)
+    }
+}
+
+struct SoftwareCollector {
+    settings: Settings,
+}
+
+impl Collector for SoftwareCollector {
+    type Output = NodeSoftware;
+
+    fn name(&self) -> &'static str {
+        "software"
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(300)
+    }
+
+    fn collect(&self) -> Result<NodeSoftware, Box<dyn std::error::Error>> {
+        Ok(software::collect_software_info(&self.settings.probes))
+    }
+}
+
+struct SystemCollector;
+
+impl Collector for SystemCollector {
+    type Output = NodeSystem;
+
+    fn name(&self) -> &'static str {
+        "system"
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn collect(&self) -> Result<NodeSystem, Box<dyn std::error::Error>> {
+        Ok(system::collect_system_info())
+    }
+}
+
+struct FactsCollector {
+    settings: Settings,
+}
+
+impl Collector for FactsCollector {
+    type Output = BTreeMap<String, serde_json::Value>;
+
+    fn name(&self) -> &'static str {
+        "facts"
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(120)
+    }
+
+    fn collect(&self) -> Result<Self::Output, Box<dyn std::error::Error>> {
+        facts::collect_custom_facts(&self.settings.facts)
+    }
+}
+
+struct GpuMetricsCollector {
+    settings: Settings,
+}
+
+impl Collector for GpuMetricsCollector {
+    type Output = Vec<GpuMetrics>;
+
+    fn name(&self) -> &'static str {
+        "gpu_metrics"
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(120)
+    }
+
+    fn collect(&self) -> Result<Vec<GpuMetrics>, Box<dyn std::error::Error>> {
+        Ok(gpu_metrics::collect_gpu_metrics(&self.settings.probes))
+    }
+}
+
+struct HostMetricsCollector {
+    sys: Arc<Mutex<System>>,
+}
+
+impl Collector for HostMetricsCollector {
+    type Output = HostMetrics;
+
+    fn name(&self) -> &'static str {
+        "metrics"
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(30)
+    }
+
+    fn collect(&self) -> Result<HostMetrics, Box<dyn std::error::Error>> {
+        Ok(metrics::collect_host_metrics(&self.sys))
+    }
+}
+
+struct KernelLogCollector {
+    settings: Settings,
+}
+
+impl Collector for KernelLogCollector {
+    type Output = KernelLogReport;
+
+    fn name(&self) -> &'static str {
+        "kernel_log"
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn collect(&self) -> Result<KernelLogReport, Box<dyn std::error::Error>> {
+        kernel_log::collect_kernel_log(&self.settings.probes)
+    }
+}
+
+pub(crate) fn collect_inventory(settings: &Settings) -> Inventory {
+    for name in &settings.collectors.disabled {
+        if !BUILTIN_COLLECTORS.contains(&name.as_str()) {
+            warn!("Ignoring unknown collector {name} in disabled collectors");
+        }
+    }
+
+    let mut collectors = BTreeMap::new();
+    // the hardware and metrics collectors read the same `sysinfo` state
+    let sys = Arc::new(Mutex::new(System::new()));
+    let hardware = run_collector(
+        HardwareCollector {
+            settings: settings.clone(),
+            sys: Arc::clone(&sys),
+        },
+        &settings.collectors,
+        &mut collectors,
+    );
+    let software = run_collector(
+        SoftwareCollector {
+            settings: settings.clone(),
+        },
+        &settings.collectors,
+        &mut collectors,
+    );
+    let system = run_collector(SystemCollector, &settings.collectors, &mut collectors);
+    let custom = run_collector(
+        FactsCollector {
+            settings: settings.clone(),
+        },
+        &settings.collectors,
+        &mut collectors,
+    );
+    let gpu_metrics = run_collector(
+        GpuMetricsCollector {
+            settings: settings.clone(),
+        },
+        &settings.collectors,
+        &mut collectors,
+    );
+    let metrics = run_collector(
+        HostMetricsCollector { sys },
+        &settings.collectors,
+        &mut collectors,
+    );
+    let kernel_log = run_collector(
+        KernelLogCollector {
+            settings: settings.clone(),
+        },
+        &settings.collectors,
+        &mut collectors,
+    );
+
+    let mut inventory = Inventory {
+        hardware,
+        software,
+        system,
+        custom,
+        gpu_metrics,
+        metrics,
+        kernel_log,
+        health: None,
+        changes: None,
+        collectors,
+    };
+    inventory.health = Some(health::evaluate(&inventory, &settings.health));
+    inventory
+}
+
+fn run_collector<C: Collector>(
+    collector: C,
+    settings: &CollectorSettings,
+    reports: &mut BTreeMap<String, CollectorReport>,
+) -> Option<C::Output> {
+    let name = collector.name();
+    if settings.disabled.iter().any(|disabled| disabled == name) {
+        info!("Collector {name} is disabled");
+        reports.insert(
+            name.to_string(),
+            CollectorReport {
+                status: CollectorStatus::Disabled,
+                error: None,
+                duration_ms: 0,
+            },
+        );
+        return None;
+    }
+
+    let timeout = settings
+        .timeout_secs
+        .get(name)
+        .map_or_else(|| collector.timeout(), |secs| Duration::from_secs(*secs));
+
+    let started = Instant::now();
+    let (tx, rx) = mpsc::channel();
+    let spawned = thread::Builder::new()
+        .name(format!("collector-{name}"))
+        .spawn(move || {
+            let _ = tx.send(collector.collect().map_err(|e| e.to_string()));
+        });
+
+    let (output, status, error) = match spawned {
+        Err(e) => (
+            None,
+            CollectorStatus::Failed,
+            Some(format!("failed starting collector: {e}")),
+        ),
+        Ok(_) => match rx.recv_timeout(timeout) {
+            Ok(Ok(output)) => (Some(output), CollectorStatus::Ok, None),
+            Ok(Err(e)) => (None, CollectorStatus::Failed, Some(e)),
+            // the sender is dropped without a result if the collector panicked
+            Err(mpsc::RecvTimeoutError::Disconnected) => (
+                None,
+                CollectorStatus::Failed,
+                Some(String::from("collector panicked")),
+            ),
+            Err(mpsc::RecvTimeoutError::Timeout) => (
+                None,
+                CollectorStatus::TimedOut,
+                Some(format!("timed out after {} seconds", timeout.as_secs())),
+            ),
+        },
+    };
+
+    if let Some(e) = &error {
+        error!("Collector {name} failed: {e}");
+    }
+
+    reports.insert(
+        name.to_string(),
+        CollectorReport {
+            status,
+            error,
+            duration_ms: started.elapsed().as_millis() as u64,
+        },
+    );
+    output
+}
+
+#[cfg(test)]
+impl Inventory {
+    /// Inventory of a single GPU node as a test fixture.
+    pub(crate) fn mock() -> Self {
+        use crate::dmi::NodeDmi;
+        use crate::network::NodeNetwork;
+        use crate::software::{GpuRuntimeIntegration, SoftwareVersions};
+        use crate::storage::NodeStorage;
+        use crate::system::{OsRelease, Virtualization};
+        use crate::topology::NodeTopology;
+
+        Inventory {
+            hardware: Some(NodeHardware {
+                gpu_count: 1,
+                gpu_vendor: String::from("Nvidia"),
+                gpu_type: String::from("AD102GL [L40]"),
+                gpu_memory: 48,
+                cpu_cores: 16,
+                memory_gb: 64,
+                storage_gb: 1024,
+                gpus: Vec::new(),
+                topology: NodeTopology::default(),
+                interconnect: None,
+                storage: NodeStorage::default(),
+                network: NodeNetwork::default(),
+                dmi: NodeDmi::default(),
+            }),
+            software: Some(NodeSoftware {
+                docker: String::from(""),
+                nvidia: String::from(""),
+                amd: String::from(""),
+                versions: SoftwareVersions::default(),
+                tools: BTreeMap::new(),
+                gpu_runtime: GpuRuntimeIntegration::default(),
+                probes: BTreeMap::new(),
+            }),
+            system: Some(NodeSystem {
+                os: String::from("Linux (Ubuntu 24.04)"),
+                kernel: String::from("Linux 6.11.0-26-generic"),
+                os_release: OsRelease::default(),
+                architecture: String::from("x86_64"),
+                kernel_release: String::from("6.11.0-26-generic"),
+                kernel_version: String::from("#26~24.04.1-Ubuntu SMP PREEMPT_DYNAMIC"),
+                hostname: Some(String::from("node-1")),
+                timezone: Some(String::from("Etc/UTC")),
+                boot_time: 1_700_000_000,
+                uptime_secs: 3600,
+                virtualization: Virtualization::default(),
+            }),
+            custom: Some(BTreeMap::from([(
+                String::from("rack"),
+                serde_json::json!({ "row": "B", "position": 12 }),
+            )])),
+            gpu_metrics: Some(Vec::new()),
+            metrics: None,
+            kernel_log: None,
+            health: None,
+            changes: None,
+            collectors: BTreeMap::from([(
+                String::from("hardware"),
+                CollectorReport {
+                    status: CollectorStatus::Ok,
+                    error: None,
+                    duration_ms: 1200,
+                },
+            )]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeCollector {
+        result: Result<u32, &'static str>,
+        delay: Duration,
+    }
+
+    impl Collector for FakeCollector {
+        type Output = u32;
+
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn timeout(&self) -> Duration {
+            Duration::from_millis(200)
+        }
+
+        fn collect(&self) -> Result<u32, Box<dyn std::error::Error>> {
+            thread::sleep(self.delay);
+            self.result.map_err(|e| e.into())
+        }
+    }
+
+    fn run_fake(
+        result: Result<u32, &'static str>,
+        delay: Duration,
+        settings: &CollectorSettings,
+    ) -> (Option<u32>, CollectorReport) {
+        let mut reports = BTreeMap::new();
+        let output = run_collector(FakeCollector { result, delay }, settings, &mut reports);
+        (output, reports.remove("fake").unwrap())
+    }
+
+    #[test]
+    fn test_run_collector_reports_status() {
+        let settings = CollectorSettings::default();
+
+        let (output, report) = run_fake(Ok(7), Duration::ZERO, &settings);
+        assert_eq!(output, Some(7));
+        assert_eq!(report.status, CollectorStatus::Ok);
+
+        let (output, report) = run_fake(Err("class unreadable"), Duration::ZERO, &settings);
+        assert_eq!(output, None);
+        assert_eq!(report.status, CollectorStatus::Failed);
+        assert_eq!(report.error.as_deref(), Some("class unreadable"));
+
+        let (output, report) = run_fake(Ok(7), Duration::from_secs(2), &settings);
+        assert_eq!(output, None);
+        assert_eq!(report.status, CollectorStatus::TimedOut);
+    }
+
+    #[test]
+    fn test_run_collector_honours_settings() {
+        let settings = CollectorSettings {
+            disabled: vec![String::from("fake")],
+            ..CollectorSettings::default()
+        };
+        let (output, report) = run_fake(Ok(7), Duration::ZERO, &settings);
+        assert_eq!(output, None);
+        assert_eq!(report.status, CollectorStatus::Disabled);
+
+        let settings = CollectorSettings {
+            timeout_secs: BTreeMap::from([(String::from("fake"), 5)]),
+            ..CollectorSettings::default()
+        };
+        let (output, report) = run_fake(Ok(7), Duration::from_millis(400), &settings);
+        assert_eq!(output, Some(7));
+        assert_eq!(report.status, CollectorStatus::Ok);
+    }
+}
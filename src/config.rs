@@ -1,9 +1,11 @@
 use dotenvy::from_path;
 use log::{error, info};
-use std::fs::OpenOptions;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::{OpenOptions, Permissions};
 use std::io::Write;
-use std::os::unix::fs::OpenOptionsExt;
-use std::path::PathBuf;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 use std::{env, fs};
 
 pub(crate) fn config_file_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
@@ -15,6 +17,351 @@ pub(crate) fn config_file_path() -> Result<PathBuf, Box<dyn std::error::Error>>
     Ok(file)
 }
 
+/// Directory of the config file, also holding the node's keys.
+pub(crate) fn config_dir_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let config_file = config_file_path()?;
+    Ok(config_file
+        .parent()
+        .ok_or("config file has no directory")?
+        .to_path_buf())
+}
+
+/// Writes a file only the owner can read. The mode is also tightened if the
+/// file already existed, before the content is written.
+pub(crate) fn write_private_file(
+    path: &Path,
+    content: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.write_all(content)?;
+    Ok(())
+}
+
+/// Directory for state kept between runs, e.g. `~/.local/state/exalsius`.
+pub(crate) fn state_dir_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let dir = dirs::state_dir()
+        .or_else(|| dirs::home_dir().map(|home| home.join(".local").join("state")))
+        .ok_or("HOME not set")?
+        .join("exalsius");
+
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+pub(crate) fn settings_file_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    Ok(config_file_path()?.with_file_name("config.toml"))
+}
+
+/// Optional settings that tune the collection. Every key has a default, so a
+/// missing file or section behaves like the previous hardcoded behaviour.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub(crate) struct Settings {
+    pub storage: StorageSettings,
+    pub probes: ProbeSettings,
+    pub collectors: CollectorSettings,
+    pub facts: FactsSettings,
+    /// Node labels sent with every request, e.g. `region = "eu-central"`.
+    pub labels: BTreeMap<String, String>,
+    pub health: HealthSettings,
+    pub spool: SpoolSettings,
+    pub heartbeat: HeartbeatSettings,
+    pub logging: LoggingSettings,
+    pub http: HttpSettings,
+    pub mtls: MtlsSettings,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct StorageSettings {
+    /// Mount points counted as job storage. An entry ending in `*` matches
+    /// every mount point with that prefix, e.g. `/mnt/*`.
+    pub job_storage_mounts: Vec<String>,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            job_storage_mounts: vec![String::from("/")],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct ProbeSettings {
+    /// Seconds an external tool such as `nvidia-smi` may run before it is killed.
+    pub timeout_secs: u64,
+    /// Bytes of stdout and stderr kept per tool invocation.
+    pub max_output_bytes: usize,
+}
+
+impl Default for ProbeSettings {
+    fn default() -> Self {
+        ProbeSettings {
+            timeout_secs: 20,
+            max_output_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub(crate) struct CollectorSettings {
+    /// Names of collectors that are not run, e.g. `["software"]`.
+    pub disabled: Vec<String>,
+    /// Overrides the built-in timeout of a collector, keyed by its name.
+    pub timeout_secs: BTreeMap<String, u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct FactsSettings {
+    /// Directory with custom fact files and scripts. Defaults to `facts.d`
+    /// next to the configuration file.
+    pub directory: Option<PathBuf>,
+    /// Seconds a fact script may run before it is killed.
+    pub timeout_secs: u64,
+    /// Largest fact file or script output that is accepted.
+    pub max_bytes: usize,
+}
+
+impl Default for FactsSettings {
+    fn default() -> Self {
+        FactsSettings {
+            directory: None,
+            timeout_secs: 10,
+            max_bytes: 64 * 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct HealthSettings {
+    /// Number of GPUs the node must have. Defaults to the count at registration.
+    pub expected_gpu_count: Option<u8>,
+    /// Free space on job storage below which the node is unhealthy.
+    pub min_free_disk_percent: f32,
+    /// Free space on job storage below which a warning is reported.
+    pub warn_free_disk_percent: f32,
+    /// Largest tolerated clock error reported by the kernel.
+    pub max_clock_error_ms: u64,
+    pub docker_socket: PathBuf,
+}
+
+impl Default for HealthSettings {
+    fn default() -> Self {
+        HealthSettings {
+            expected_gpu_count: None,
+            min_free_disk_percent: 10.0,
+            warn_free_disk_percent: 20.0,
+            max_clock_error_ms: 1000,
+            docker_socket: PathBuf::from("/var/run/docker.sock"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct SpoolSettings {
+    /// Failed heartbeats kept for replay. `0` disables the spool.
+    pub max_entries: usize,
+    /// Spooled heartbeats older than this are dropped instead of replayed.
+    pub max_age_hours: u64,
+}
+
+impl Default for SpoolSettings {
+    fn default() -> Self {
+        SpoolSettings {
+            max_entries: 96,
+            max_age_hours: 24,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct HeartbeatSettings {
+    /// Minutes between two runs of the systemd timer.
+    pub interval_minutes: u32,
+}
+
+impl Default for HeartbeatSettings {
+    fn default() -> Self {
+        HeartbeatSettings {
+            interval_minutes: 15,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct LoggingSettings {
+    /// Default log level, `RUST_LOG` takes precedence.
+    pub level: String,
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        LoggingSettings {
+            level: String::from("info"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct HttpSettings {
+    /// Proxy for all requests, overriding `HTTPS_PROXY` and `HTTP_PROXY`.
+    pub proxy: Option<String>,
+    /// Hosts reached without the proxy, overriding `NO_PROXY`.
+    pub no_proxy: Option<String>,
+    /// PEM file with certificates trusted in addition to the bundled roots.
+    pub ca_bundle: Option<PathBuf>,
+    /// SHA-256 fingerprints of certificates of which one must be in the
+    /// chain of the server.
+    pub pinned_certificates: Vec<String>,
+    pub connect_timeout_secs: u64,
+    pub timeout_secs: u64,
+    /// Appended to the User-Agent, which always names the tool and version.
+    pub user_agent: Option<String>,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            proxy: None,
+            no_proxy: None,
+            ca_bundle: None,
+            pinned_certificates: Vec::new(),
+            connect_timeout_secs: 10,
+            timeout_secs: 60,
+            user_agent: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct MtlsSettings {
+    /// Request a client certificate at registration and present it on every
+    /// connection to the API.
+    pub enabled: bool,
+    /// Days before expiry at which the client certificate is renewed.
+    pub renew_before_days: u64,
+}
+
+impl Default for MtlsSettings {
+    fn default() -> Self {
+        MtlsSettings {
+            enabled: false,
+            renew_before_days: 30,
+        }
+    }
+}
+
+pub(crate) fn load_settings() -> Result<Settings, Box<dyn std::error::Error>> {
+    let path = settings_file_path()?;
+    load_settings_from_path(&path)
+}
+
+fn load_settings_from_path(path: &PathBuf) -> Result<Settings, Box<dyn std::error::Error>> {
+    if !path.exists() {
+        info!("No settings file found. Using default settings");
+        return Ok(Settings::default());
+    }
+
+    info!("Loading settings from {}", path.display());
+    let content = fs::read_to_string(path)?;
+    let settings = toml::from_str::<Settings>(&content).map_err(|e| {
+        error!("Failed parsing settings file {}: {e}", path.display());
+        e
+    })?;
+    Ok(settings)
+}
+
+/// Replaces one top-level table of the settings file and keeps the others.
+/// Comments in the file are not preserved.
+pub(crate) fn update_settings_table(
+    key: &str,
+    value: toml::Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = settings_file_path()?;
+    update_settings_table_at(&path, key, value)
+}
+
+fn update_settings_table_at(
+    path: &PathBuf,
+    key: &str,
+    value: toml::Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    edit_settings_at(path, |table| {
+        table.insert(key.to_string(), value);
+    })?;
+    info!("Updated [{key}] in settings file {}", path.display());
+    Ok(())
+}
+
+/// Sets a single key of a table in the settings file, keeping the other keys
+/// of the table.
+pub(crate) fn update_setting(
+    table: &str,
+    key: &str,
+    value: toml::Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = settings_file_path()?;
+    update_setting_at(&path, table, key, value)
+}
+
+fn update_setting_at(
+    path: &PathBuf,
+    table: &str,
+    key: &str,
+    value: toml::Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    edit_settings_at(path, |settings| {
+        let entry = settings
+            .entry(table)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if !entry.is_table() {
+            *entry = toml::Value::Table(toml::Table::new());
+        }
+        if let Some(entry) = entry.as_table_mut() {
+            entry.insert(key.to_string(), value);
+        }
+    })?;
+    info!(
+        "Updated {key} in [{table}] of settings file {}",
+        path.display()
+    );
+    Ok(())
+}
+
+fn edit_settings_at(
+    path: &PathBuf,
+    edit: impl FnOnce(&mut toml::Table),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut table = if path.exists() {
+        fs::read_to_string(path)?.parse::<toml::Table>()?
+    } else {
+        toml::Table::new()
+    };
+    edit(&mut table);
+
+    fs::write(path, toml::to_string(&table)?).map_err(|e| {
+        error!("Failed writing settings file {}: {e}", path.display());
+        e
+    })?;
+    Ok(())
+}
+
 pub(crate) fn lookup_configuration(
     node_id: Option<String>,
     api_url: Option<String>,
@@ -181,3 +528,69 @@ pub fn write_new_auth_token(new_auth_token: &String) -> Result<(), Box<dyn std::
     info!("Successfully wrote new auth token to config file");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_settings_defaults_without_file() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let settings = load_settings_from_path(&temp_dir.path().join("config.toml"))
+            .expect("missing settings file should not fail");
+
+        assert_eq!(settings.storage.job_storage_mounts, vec!["/"]);
+    }
+
+    #[test]
+    fn test_load_settings_from_file() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let path = temp_dir.path().join("config.toml");
+        fs::write(
+            &path,
+            concat!(
+                "[storage]\njob_storage_mounts = [\"/scratch\", \"/mnt/*\"]\n",
+                "[probes]\ntimeout_secs = 5\n",
+                "[collectors]\ndisabled = [\"software\"]\n",
+                "[collectors.timeout_secs]\nhardware = 600\n",
+            ),
+        )
+        .unwrap();
+
+        let settings = load_settings_from_path(&path).expect("settings should parse");
+
+        assert_eq!(
+            settings.storage.job_storage_mounts,
+            vec!["/scratch", "/mnt/*"]
+        );
+        assert_eq!(settings.probes.timeout_secs, 5);
+        assert_eq!(settings.probes.max_output_bytes, 1024 * 1024);
+        assert_eq!(settings.collectors.disabled, vec!["software"]);
+        assert_eq!(settings.collectors.timeout_secs.get("hardware"), Some(&600));
+    }
+
+    #[test]
+    fn test_update_settings_table_keeps_other_tables() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let path = temp_dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "[probes]\ntimeout_secs = 5\n\n[labels]\nregion = \"us\"\n",
+        )
+        .unwrap();
+
+        let labels = BTreeMap::from([(String::from("tier"), String::from("gold"))]);
+        update_settings_table_at(&path, "labels", toml::Value::try_from(&labels).unwrap())
+            .expect("settings should be updated");
+
+        let settings = load_settings_from_path(&path).expect("settings should parse");
+        assert_eq!(settings.probes.timeout_secs, 5);
+        assert_eq!(settings.labels, labels);
+
+        update_setting_at(&path, "probes", "max_output_bytes", toml::Value::from(4096))
+            .expect("settings should be updated");
+        let settings = load_settings_from_path(&path).expect("settings should parse");
+        assert_eq!(settings.probes.timeout_secs, 5);
+        assert_eq!(settings.probes.max_output_bytes, 4096);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::collector::BUILTIN_COLLECTORS;
+use crate::config::{self, Settings};
+use crate::self_register;
+use log::{LevelFilter, info, warn};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Longest heartbeat interval the server may set, one day.
+const MAX_INTERVAL_MINUTES: u32 = 24 * 60;
+
+/// Settings the server can change with its response to a heartbeat, so the
+/// fleet is tuned centrally. Absent fields leave the setting as it is and
+/// unknown fields are ignored.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub(crate) struct ServerDirectives {
+    pub heartbeat_interval_minutes: Option<u32>,
+    /// Collectors to enable (`true`) or disable (`false`) by name.
+    pub collectors: BTreeMap<String, bool>,
+    pub log_level: Option<String>,
+}
+
+/// A settings file key changed by a directive.
+#[derive(Debug, PartialEq)]
+struct SettingUpdate {
+    table: &'static str,
+    key: &'static str,
+    value: toml::Value,
+}
+
+/// Persists the directives that differ from the current settings. They take
+/// effect with the next run, a new interval also reschedules the systemd
+/// timer.
+pub(crate) fn apply(directives: &ServerDirectives, settings: &Settings) {
+    apply_with(
+        settings_updates(directives, settings),
+        self_register::reschedule_systemd_timer,
+        |update| config::update_setting(update.table, update.key, update.value.clone()),
+    );
+}
+
+/// A new interval is only persisted once the timer runs with it. Otherwise
+/// the settings would already match the directive and the next run would not
+/// try again.
+fn apply_with(
+    updates: Vec<SettingUpdate>,
+    reschedule: impl Fn(u32) -> Result<(), Box<dyn std::error::Error>>,
+    mut persist: impl FnMut(&SettingUpdate) -> Result<(), Box<dyn std::error::Error>>,
+) {
+    for update in updates {
+        if update.table == "heartbeat"
+            && let Some(minutes) = update.value.as_integer()
+            && let Err(e) = reschedule(minutes as u32)
+        {
+            warn!("Failed rescheduling the systemd timer: {}", e);
+            continue;
+        }
+
+        info!(
+            "Server set {} in [{}] to {}",
+            update.key, update.table, update.value
+        );
+        if let Err(e) = persist(&update) {
+            warn!("Failed applying server directive: {}", e);
+        }
+    }
+}
+
+fn settings_updates(directives: &ServerDirectives, settings: &Settings) -> Vec<SettingUpdate> {
+    let mut updates = Vec::new();
+
+    if let Some(minutes) = directives.heartbeat_interval_minutes {
+        if !(1..=MAX_INTERVAL_MINUTES).contains(&minutes) {
+            warn!("Ignoring heartbeat interval of {minutes} minutes from the server");
+        } else if minutes != settings.heartbeat.interval_minutes {
+            updates.push(SettingUpdate {
+                table: "heartbeat",
+                key: "interval_minutes",
+                value: toml::Value::from(minutes),
+            });
+        }
+    }
+
+    let mut disabled = settings.collectors.disabled.clone();
+    for (name, enabled) in &directives.collectors {
+        if !BUILTIN_COLLECTORS.contains(&name.as_str()) {
+            warn!("Ignoring unknown collector {name} from the server");
+        } else if *enabled {
+            disabled.retain(|disabled| disabled != name);
+        } else if !disabled.contains(name) {
+            disabled.push(name.to_owned());
+        }
+    }
+    if disabled != settings.collectors.disabled {
+        updates.push(SettingUpdate {
+            table: "collectors",
+            key: "disabled",
+            value: toml::Value::from(disabled),
+        });
+    }
+
+    if let Some(level) = &directives.log_level {
+        match level.parse::<LevelFilter>() {
+            Ok(level) if !settings.logging.level.eq_ignore_ascii_case(level.as_str()) => {
+                updates.push(SettingUpdate {
+                    table: "logging",
+                    key: "level",
+                    value: toml::Value::from(level.as_str().to_lowercase()),
+                });
+            }
+            Ok(_) => {}
+            Err(_) => warn!("Ignoring unknown log level {level} from the server"),
+        }
+    }
+
+    updates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_updates() {
+        let mut settings = Settings::default();
+        settings.collectors.disabled = vec![String::from("kernel_log")];
+
+        let directives: ServerDirectives = serde_json::from_value(serde_json::json!({
+            "heartbeat_interval_minutes": 5,
+            "collectors": { "kernel_log": true, "metrics": false, "bogus": false },
+            "log_level": "DEBUG",
+            "maintenance_window": "sunday"
+        }))
+        .unwrap();
+
+        assert_eq!(
+            settings_updates(&directives, &settings),
+            vec![
+                SettingUpdate {
+                    table: "heartbeat",
+                    key: "interval_minutes",
+                    value: toml::Value::from(5),
+                },
+                SettingUpdate {
+                    table: "collectors",
+                    key: "disabled",
+                    value: toml::Value::from(vec!["metrics"]),
+                },
+                SettingUpdate {
+                    table: "logging",
+                    key: "level",
+                    value: toml::Value::from("debug"),
+                },
+            ]
+        );
+
+        // directives matching the settings or out of range change nothing
+        let directives = ServerDirectives {
+            heartbeat_interval_minutes: Some(0),
+            collectors: BTreeMap::from([(String::from("kernel_log"), false)]),
+            log_level: Some(String::from("info")),
+        };
+        assert!(settings_updates(&directives, &settings).is_empty());
+    }
+
+    #[test]
+    fn test_interval_kept_when_rescheduling_fails() {
+        let updates = || {
+            vec![
+                SettingUpdate {
+                    table: "heartbeat",
+                    key: "interval_minutes",
+                    value: toml::Value::from(5),
+                },
+                SettingUpdate {
+                    table: "logging",
+                    key: "level",
+                    value: toml::Value::from("debug"),
+                },
+            ]
+        };
+
+        let mut persisted = Vec::new();
+        apply_with(
+            updates(),
+            |_| Err("systemctl failed".into()),
+            |update| {
+                persisted.push(update.key);
+                Ok(())
+            },
+        );
+        assert_eq!(persisted, vec!["level"]);
+
+        let mut persisted = Vec::new();
+        apply_with(
+            updates(),
+            |minutes| {
+                assert_eq!(minutes, 5);
+                Ok(())
+            },
+            |update| {
+                persisted.push(update.key);
+                Ok(())
+            },
+        );
+        assert_eq!(persisted, vec!["interval_minutes", "level"]);
+    }
+}
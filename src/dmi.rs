@@ -0,0 +1,375 @@
+use log::{info, warn};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+const SMBIOS_TYPE_MEMORY_ARRAY: u8 = 16;
+const SMBIOS_TYPE_MEMORY_DEVICE: u8 = 17;
+const SMBIOS_TYPE_END_OF_TABLE: u8 = 127;
+
+#[derive(Serialize, Debug, Default)]
+pub struct NodeDmi {
+    pub system_manufacturer: Option<String>,
+    pub product_name: Option<String>,
+    pub product_version: Option<String>,
+    pub board_vendor: Option<String>,
+    pub board_name: Option<String>,
+    pub bios_vendor: Option<String>,
+    pub bios_version: Option<String>,
+    pub bios_date: Option<String>,
+    pub chassis_vendor: Option<String>,
+    pub chassis_type: Option<String>,
+    pub memory_error_correction: Option<String>,
+    pub memory_slots: usize,
+    pub memory_modules: Vec<MemoryModule>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct MemoryModule {
+    pub locator: Option<String>,
+    pub bank_locator: Option<String>,
+    pub size_mb: u64,
+    pub memory_type: String,
+    pub speed_mts: Option<u32>,
+    pub configured_speed_mts: Option<u32>,
+    pub manufacturer: Option<String>,
+    pub part_number: Option<String>,
+    pub ecc: Option<bool>,
+}
+
+pub fn collect_dmi() -> NodeDmi {
+    info!("Start collecting DMI information");
+
+    let mut dmi = read_dmi_id(Path::new("/sys/class/dmi/id"));
+
+    // the raw tables are only readable by root
+    match fs::read("/sys/firmware/dmi/tables/DMI") {
+        Ok(table) => {
+            let memory = parse_smbios_memory(&table);
+            dmi.memory_error_correction = memory.error_correction;
+            dmi.memory_slots = memory.slots;
+            dmi.memory_modules = memory.modules;
+        }
+        Err(e) => warn!("Failed reading SMBIOS tables: {e}"),
+    }
+
+    info!(
+        "System {} {} with BIOS {} ({})",
+        dmi.system_manufacturer.as_deref().unwrap_or("unknown"),
+        dmi.product_name.as_deref().unwrap_or("unknown"),
+        dmi.bios_version.as_deref().unwrap_or("unknown"),
+        dmi.bios_date.as_deref().unwrap_or("unknown")
+    );
+    for module in &dmi.memory_modules {
+        info!(
+            "Memory module {}: {} MB {} at {} MT/s",
+            module.locator.as_deref().unwrap_or("unknown"),
+            module.size_mb,
+            module.memory_type,
+            module
+                .configured_speed_mts
+                .or(module.speed_mts)
+                .unwrap_or(0)
+        );
+    }
+
+    info!("Finished collecting DMI information");
+    dmi
+}
+
+pub(crate) fn read_dmi_id(id_root: &Path) -> NodeDmi {
+    let read = |name: &str| {
+        fs::read_to_string(id_root.join(name))
+            .ok()
+            .map(|s| s.trim().to_string())
+            // vendors love to leave placeholders in unused fields
+            .filter(|s| !s.is_empty() && s != "To Be Filled By O.E.M." && s != "Default string")
+    };
+
+    NodeDmi {
+        system_manufacturer: read("sys_vendor"),
+        product_name: read("product_name"),
+        product_version: read("product_version"),
+        board_vendor: read("board_vendor"),
+        board_name: read("board_name"),
+        bios_vendor: read("bios_vendor"),
+        bios_version: read("bios_version"),
+        bios_date: read("bios_date"),
+        chassis_vendor: read("chassis_vendor"),
+        chassis_type: read("chassis_type")
+            .and_then(|t| t.parse::<u8>().ok())
+            .map(chassis_type_name),
+        ..NodeDmi::default()
+    }
+}
+
+fn chassis_type_name(code: u8) -> String {
+    match code {
+        1 => String::from("Other"),
+        2 => String::from("Unknown"),
+        3 => String::from("Desktop"),
+        4 => String::from("Low Profile Desktop"),
+        6 => String::from("Mini Tower"),
+        7 => String::from("Tower"),
+        9 => String::from("Laptop"),
+        10 => String::from("Notebook"),
+        13 => String::from("All in One"),
+        17 => String::from("Main Server Chassis"),
+        23 => String::from("Rack Mount Chassis"),
+        25 => String::from("Multi-system Chassis"),
+        28 => String::from("Blade"),
+        29 => String::from("Blade Enclosure"),
+        35 => String::from("Mini PC"),
+        _ => format!("Type {code}"),
+    }
+}
+
+struct SmbiosStructure<'a> {
+    kind: u8,
+    formatted: &'a [u8],
+    strings: Vec<&'a [u8]>,
+}
+
+impl SmbiosStructure<'_> {
+    fn byte(&self, offset: usize) -> Option<u8> {
+        self.formatted.get(offset).copied()
+    }
+
+    fn word(&self, offset: usize) -> Option<u16> {
+        let bytes = self.formatted.get(offset..offset + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn dword(&self, offset: usize) -> Option<u32> {
+        let bytes = self.formatted.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// String fields hold a 1-based index into the string set, 0 means none.
+    fn string(&self, offset: usize) -> Option<String> {
+        let index = self.byte(offset)? as usize;
+        let s = String::from_utf8_lossy(self.strings.get(index.checked_sub(1)?)?)
+            .trim()
+            .to_string();
+        (!s.is_empty() && s != "Not Specified" && s != "Unknown").then_some(s)
+    }
+}
+
+fn parse_smbios_structures(table: &[u8]) -> Vec<SmbiosStructure<'_>> {
+    let mut structures = Vec::new();
+    let mut offset = 0;
+
+    while offset + 4 <= table.len() {
+        let kind = table[offset];
+        let length = table[offset + 1] as usize;
+        if length < 4 || offset + length > table.len() {
+            break;
+        }
+        let formatted = &table[offset..offset + length];
+
+        // the string set follows the formatted area and ends with two NULs
+        let mut end = offset + length;
+        while end + 1 < table.len() && !(table[end] == 0 && table[end + 1] == 0) {
+            end += 1;
+        }
+        let strings = table[offset + length..end]
+            .split(|b| *b == 0)
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        structures.push(SmbiosStructure {
+            kind,
+            formatted,
+            strings,
+        });
+        if kind == SMBIOS_TYPE_END_OF_TABLE {
+            break;
+        }
+        offset = end + 2;
+    }
+
+    structures
+}
+
+#[derive(Debug, Default)]
+struct SmbiosMemory {
+    error_correction: Option<String>,
+    slots: usize,
+    modules: Vec<MemoryModule>,
+}
+
+/// Extracts the memory arrays (type 16) and memory devices (type 17) from a
+/// raw SMBIOS table as found in `/sys/firmware/dmi/tables/DMI`.
+fn parse_smbios_memory(table: &[u8]) -> SmbiosMemory {
+    let mut memory = SmbiosMemory::default();
+
+    for structure in parse_smbios_structures(table) {
+        match structure.kind {
+            SMBIOS_TYPE_MEMORY_ARRAY => {
+                let error_correction = structure.byte(0x06).map(error_correction_name);
+                // system ROM arrays are listed as well; keep the first system memory array
+                if structure.byte(0x05) == Some(0x03) && memory.error_correction.is_none() {
+                    memory.error_correction = error_correction;
+                }
+            }
+            SMBIOS_TYPE_MEMORY_DEVICE => {
+                memory.slots += 1;
+                if let Some(module) = parse_memory_device(&structure) {
+                    memory.modules.push(module);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    memory
+}
+
+fn parse_memory_device(structure: &SmbiosStructure<'_>) -> Option<MemoryModule> {
+    let size_mb = match structure.word(0x0C)? {
+        // empty slot or unknown size
+        0 | 0xFFFF => return None,
+        0x7FFF => structure.dword(0x1C)? as u64 & 0x7FFF_FFFF,
+        size if size & 0x8000 != 0 => (size & 0x7FFF) as u64 / 1024,
+        size => size as u64,
+    };
+
+    let speed = |offset: usize, extended_offset: usize| match structure.word(offset) {
+        Some(0) | None => None,
+        Some(0xFFFF) => structure.dword(extended_offset),
+        Some(speed) => Some(speed as u32),
+    };
+
+    let ecc = match (structure.word(0x08), structure.word(0x0A)) {
+        (Some(total), Some(data)) if total != 0xFFFF && data != 0xFFFF => Some(total > data),
+        _ => None,
+    };
+
+    Some(MemoryModule {
+        locator: structure.string(0x10),
+        bank_locator: structure.string(0x11),
+        size_mb,
+        memory_type: structure
+            .byte(0x12)
+            .map(memory_type_name)
+            .unwrap_or_else(|| String::from("Unknown")),
+        speed_mts: speed(0x15, 0x54),
+        configured_speed_mts: speed(0x20, 0x58),
+        manufacturer: structure.string(0x17),
+        part_number: structure.string(0x1A),
+        ecc,
+    })
+}
+
+fn memory_type_name(code: u8) -> String {
+    match code {
+        0x03 => String::from("DRAM"),
+        0x0F => String::from("SDRAM"),
+        0x12 => String::from("DDR"),
+        0x13 => String::from("DDR2"),
+        0x18 => String::from("DDR3"),
+        0x1A => String::from("DDR4"),
+        0x1B => String::from("LPDDR"),
+        0x1C => String::from("LPDDR2"),
+        0x1D => String::from("LPDDR3"),
+        0x1E => String::from("LPDDR4"),
+        0x20 => String::from("HBM"),
+        0x21 => String::from("HBM2"),
+        0x22 => String::from("DDR5"),
+        0x23 => String::from("LPDDR5"),
+        0x24 => String::from("HBM3"),
+        0x02 => String::from("Unknown"),
+        _ => format!("Type {code:#04x}"),
+    }
+}
+
+fn error_correction_name(code: u8) -> String {
+    match code {
+        0x03 => String::from("None"),
+        0x04 => String::from("Parity"),
+        0x05 => String::from("Single-bit ECC"),
+        0x06 => String::from("Multi-bit ECC"),
+        0x07 => String::from("CRC"),
+        _ => String::from("Unknown"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hand-built SMBIOS 3.x structures modelled on a DDR5 server, not a
+    /// captured table: one memory array with multi-bit ECC, a populated 64 GB
+    /// DIMM, an empty slot and the end marker.
+    const SYNTHETIC_DMI_TABLE: &[u8] = &[
+        // type 16, length 0x17, handle 0x1000
+        0x10, 0x17, 0x00, 0x10, 0x03, 0x03, 0x06, 0x00, 0x00, 0x00, 0x80, 0xFE, 0xFF, 0x10, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // type 17, length 0x5C, handle 0x1100
+        0x11, 0x5C, 0x00, 0x11, 0x00, 0x10, 0xFE, 0xFF, 0x50, 0x00, 0x40, 0x00, 0xFF, 0x7F, 0x09,
+        0x00, 0x01, 0x02, 0x22, 0x80, 0x20, 0xC0, 0x12, 0x03, 0x04, 0x00, 0x05, 0x02, 0x00, 0x00,
+        0x01, 0x00, 0x30, 0x11, 0x10, 0x04, 0x10, 0x04, 0x10, 0x04, 0x03, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, b'P', b'1', b'-', b'D', b'I', b'M', b'M', b'A', b'1', 0x00, b'P', b'0', b'_',
+        b'N', b'o', b'd', b'e', b'0', b'_', b'C', b'h', b'a', b'n', b'n', b'e', b'l', b'0', 0x00,
+        b'S', b'a', b'm', b's', b'u', b'n', b'g', 0x00, b'0', b'1', b'2', b'3', 0x00, b'M', b'3',
+        b'2', b'1', b'R', b'8', b'G', b'A', b'0', b'B', b'B', b'0', b'-', b'C', b'Q', b'K', b'E',
+        b'G', b' ', b' ', 0x00, 0x00, // type 17, length 0x28, handle 0x1101, empty slot
+        0x11, 0x28, 0x01, 0x11, 0x00, 0x10, 0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x09,
+        0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, b'P', b'1', b'-', b'D', b'I',
+        b'M', b'M', b'A', b'2', 0x00, 0x00, // type 127, end of table
+        0x7F, 0x04, 0xFF, 0xFE, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_parse_smbios_memory() {
+        let memory = parse_smbios_memory(SYNTHETIC_DMI_TABLE);
+
+        assert_eq!(memory.error_correction.as_deref(), Some("Multi-bit ECC"));
+        assert_eq!(memory.slots, 2);
+        assert_eq!(
+            memory.modules,
+            vec![MemoryModule {
+                locator: Some(String::from("P1-DIMMA1")),
+                bank_locator: Some(String::from("P0_Node0_Channel0")),
+                size_mb: 65536,
+                memory_type: String::from("DDR5"),
+                speed_mts: Some(4800),
+                configured_speed_mts: Some(4400),
+                manufacturer: Some(String::from("Samsung")),
+                part_number: Some(String::from("M321R8GA0BB0-CQKEG")),
+                ecc: Some(true),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_smbios_memory_truncated_table() {
+        let memory = parse_smbios_memory(&SYNTHETIC_DMI_TABLE[..30]);
+
+        assert_eq!(memory.slots, 0);
+        assert!(memory.modules.is_empty());
+    }
+
+    #[test]
+    fn test_read_dmi_id() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = temp_dir.path();
+        fs::write(root.join("sys_vendor"), "Supermicro\n").unwrap();
+        fs::write(root.join("product_name"), "AS -4125GS-TNRT\n").unwrap();
+        fs::write(root.join("product_version"), "To Be Filled By O.E.M.\n").unwrap();
+        fs::write(root.join("bios_version"), "2.1\n").unwrap();
+        fs::write(root.join("chassis_type"), "17\n").unwrap();
+
+        let dmi = read_dmi_id(root);
+
+        assert_eq!(dmi.system_manufacturer.as_deref(), Some("Supermicro"));
+        assert_eq!(dmi.product_name.as_deref(), Some("AS -4125GS-TNRT"));
+        assert_eq!(dmi.product_version, None);
+        assert_eq!(dmi.bios_version.as_deref(), Some("2.1"));
+        assert_eq!(dmi.chassis_type.as_deref(), Some("Main Server Chassis"));
+    }
+}
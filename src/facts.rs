@@ -0,0 +1,301 @@
+use crate::config::{self, FactsSettings, ProbeSettings};
+use crate::probe::{self, ProbeStatus};
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{self, Metadata};
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Reads the custom facts from the facts directory. Every executable, `.json`
+/// or `.toml` file yields one fact named after the file, e.g. `rack.json`
+/// becomes `custom.rack`. Facts that fail validation are skipped, a directory
+/// others could write to is not read at all.
+pub(crate) fn collect_custom_facts(
+    settings: &FactsSettings,
+) -> Result<BTreeMap<String, Value>, Box<dyn std::error::Error>> {
+    let directory = match &settings.directory {
+        Some(directory) => directory.to_owned(),
+        None => config::config_file_path()?.with_file_name("facts.d"),
+    };
+    collect_facts_from(&directory, settings)
+}
+
+fn collect_facts_from(
+    directory: &Path,
+    settings: &FactsSettings,
+) -> Result<BTreeMap<String, Value>, Box<dyn std::error::Error>> {
+    let mut facts = BTreeMap::new();
+
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            info!("No custom facts directory at {}", directory.display());
+            return Ok(facts);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let metadata = fs::metadata(directory)?;
+    check_trusted(&metadata)
+        .map_err(|e| format!("refusing facts directory {}: {e}", directory.display()))?;
+
+    info!("Start collecting custom facts from {}", directory.display());
+
+    // symlinks are kept to be rejected with a warning
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_type()
+                .is_ok_and(|file_type| file_type.is_file() || file_type.is_symlink())
+        })
+        .map(|entry| entry.path())
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        // editor backups and hidden files are no facts
+        if file_name.starts_with('.') || file_name.ends_with('~') {
+            continue;
+        }
+
+        let result = fact_name(&path).and_then(|name| {
+            if facts.contains_key(&name) {
+                return Err(format!("a fact named {name} already exists"));
+            }
+            read_fact(&path, settings).map(|value| (name, value))
+        });
+
+        match result {
+            Ok((name, value)) => {
+                info!("Custom fact {name} read from {}", path.display());
+                facts.insert(name, value);
+            }
+            Err(e) => warn!("Ignoring custom fact {}: {e}", path.display()),
+        }
+    }
+
+    info!("Finished collecting {} custom facts", facts.len());
+    Ok(facts)
+}
+
+fn fact_name(path: &Path) -> Result<String, String> {
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(String::from(
+            "fact names may only contain letters, digits, '_' and '-'",
+        ));
+    }
+    Ok(name)
+}
+
+fn read_fact(path: &Path, settings: &FactsSettings) -> Result<Value, String> {
+    let metadata = fs::symlink_metadata(path).map_err(|e| e.to_string())?;
+    if metadata.file_type().is_symlink() {
+        return Err(String::from("symlinks are not followed"));
+    }
+    check_trusted(&metadata)?;
+    let mode = metadata.mode();
+
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    let is_script = mode & 0o111 != 0;
+    if !is_script && !matches!(extension, Some("json" | "toml")) {
+        return Err(String::from(
+            "expected an executable, a .json or a .toml file",
+        ));
+    }
+
+    let content = if is_script {
+        run_fact_script(path, settings)?
+    } else {
+        if metadata.len() > settings.max_bytes as u64 {
+            return Err(format!("file exceeds {} bytes", settings.max_bytes));
+        }
+        fs::read_to_string(path).map_err(|e| e.to_string())?
+    };
+
+    // scripts print JSON whatever their name is
+    let value = if !is_script && extension == Some("toml") {
+        let value = toml::from_str::<toml::Value>(&content).map_err(|e| e.to_string())?;
+        serde_json::to_value(value).map_err(|e| e.to_string())?
+    } else {
+        serde_json::from_str::<Value>(&content).map_err(|e| e.to_string())?
+    };
+
+    if !value.is_object() {
+        return Err(String::from("a fact must be a JSON or TOML object"));
+    }
+    Ok(value)
+}
+
+/// The tool usually runs as root, so nobody but root or the user running it
+/// may change what it executes.
+fn check_trusted(metadata: &Metadata) -> Result<(), String> {
+    // geteuid cannot fail
+    let effective_uid = unsafe { libc::geteuid() };
+    check_owner_and_mode(metadata.uid(), metadata.mode(), effective_uid)
+}
+
+fn check_owner_and_mode(uid: u32, mode: u32, effective_uid: u32) -> Result<(), String> {
+    if uid != 0 && uid != effective_uid {
+        return Err(format!("owned by uid {uid} instead of root"));
+    }
+    if mode & 0o022 != 0 {
+        return Err(String::from("writable by group or others"));
+    }
+    Ok(())
+}
+
+fn run_fact_script(path: &Path, settings: &FactsSettings) -> Result<String, String> {
+    let probe_settings = ProbeSettings {
+        timeout_secs: settings.timeout_secs,
+        // one byte more than allowed to tell a full output from a truncated one
+        max_output_bytes: settings.max_bytes + 1,
+    };
+
+    let output = probe::run(&path.display().to_string(), &[], &probe_settings);
+    match output.status {
+        ProbeStatus::Ok if output.stdout.len() > settings.max_bytes => {
+            Err(format!("output exceeds {} bytes", settings.max_bytes))
+        }
+        ProbeStatus::Ok => Ok(output.stdout),
+        ProbeStatus::TimedOut => Err(format!(
+            "script timed out after {} seconds",
+            settings.timeout_secs
+        )),
+        ProbeStatus::NotFound | ProbeStatus::Failed => Err(format!(
+            "script failed: {}",
+            output.stderr.lines().next().unwrap_or("no error output")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn write_fact(dir: &Path, name: &str, content: &str, mode: u32) {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    fn settings() -> FactsSettings {
+        FactsSettings {
+            directory: None,
+            timeout_secs: 1,
+            max_bytes: 256,
+        }
+    }
+
+    #[test]
+    fn test_collect_facts_from_files_and_scripts() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let dir = temp_dir.path();
+        write_fact(dir, "rack.json", r#"{"row": "B", "position": 12}"#, 0o644);
+        write_fact(
+            dir,
+            "owner.toml",
+            "team = \"ml-platform\"\ncontract_id = \"C-1042\"\n",
+            0o600,
+        );
+        write_fact(
+            dir,
+            "location",
+            "#!/bin/sh\necho '{\"site\": \"fra1\"}'\n",
+            0o755,
+        );
+        write_fact(dir, "rack.json~", "{}", 0o644);
+
+        let facts = collect_facts_from(dir, &settings()).expect("facts should be collected");
+
+        assert_eq!(facts.len(), 3);
+        assert_eq!(facts["rack"]["position"], 12);
+        assert_eq!(facts["owner"]["team"], "ml-platform");
+        assert_eq!(facts["location"]["site"], "fra1");
+    }
+
+    #[test]
+    fn test_collect_facts_skips_invalid_facts() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let dir = temp_dir.path();
+        write_fact(dir, "list.json", "[1, 2]", 0o644);
+        write_fact(dir, "broken.json", "{\"row\":", 0o644);
+        write_fact(dir, "shared.json", "{}", 0o666);
+        write_fact(dir, "notes.txt", "row B", 0o644);
+        write_fact(
+            dir,
+            "big.json",
+            &format!("{{\"a\": \"{}\"}}", "x".repeat(300)),
+            0o644,
+        );
+        write_fact(dir, "hung", "#!/bin/sh\nsleep 30\n", 0o755);
+        write_fact(dir, "failing", "#!/bin/sh\nexit 1\n", 0o755);
+        write_fact(dir, "bad name.json", "{}", 0o644);
+
+        let facts = collect_facts_from(dir, &settings()).expect("facts should be collected");
+
+        assert!(facts.is_empty(), "unexpected facts {facts:?}");
+    }
+
+    #[test]
+    fn test_collect_facts_without_directory() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+
+        let facts = collect_facts_from(&temp_dir.path().join("facts.d"), &settings())
+            .expect("a missing directory is no error");
+
+        assert!(facts.is_empty());
+    }
+
+    #[test]
+    fn test_collect_facts_rejects_symlinks() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let dir = temp_dir.path().join("facts.d");
+        fs::create_dir(&dir).unwrap();
+        write_fact(temp_dir.path(), "rack.json", r#"{"row": "B"}"#, 0o644);
+        std::os::unix::fs::symlink(temp_dir.path().join("rack.json"), dir.join("rack.json"))
+            .unwrap();
+
+        let facts = collect_facts_from(&dir, &settings()).expect("facts should be collected");
+
+        assert!(facts.is_empty(), "unexpected facts {facts:?}");
+    }
+
+    #[test]
+    fn test_collect_facts_rejects_writable_directory() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let dir = temp_dir.path();
+        write_fact(dir, "rack.json", r#"{"row": "B"}"#, 0o644);
+
+        for mode in [0o777, 0o775] {
+            fs::set_permissions(dir, fs::Permissions::from_mode(mode)).unwrap();
+            assert!(collect_facts_from(dir, &settings()).is_err());
+        }
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(collect_facts_from(dir, &settings()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_check_owner_and_mode() {
+        assert!(check_owner_and_mode(0, 0o755, 0).is_ok());
+        assert!(check_owner_and_mode(0, 0o644, 1000).is_ok());
+        assert!(check_owner_and_mode(1000, 0o644, 1000).is_ok());
+        // another user could swap the file or directory
+        assert!(check_owner_and_mode(1000, 0o755, 0).is_err());
+        assert!(check_owner_and_mode(0, 0o775, 0).is_err());
+        assert!(check_owner_and_mode(0, 0o757, 0).is_err());
+    }
+}
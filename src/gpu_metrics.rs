@@ -0,0 +1,482 @@
+use crate::config::ProbeSettings;
+use crate::probe;
+use crate::sysfs::read_trimmed;
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const NVIDIA_SMI_QUERY: &str = "index,pci.bus_id,name,utilization.gpu,memory.used,memory.total,\
+temperature.gpu,power.draw,power.limit,clocks_throttle_reasons.active,\
+ecc.errors.corrected.volatile.total,ecc.errors.uncorrected.volatile.total";
+
+/// Bits of `clocks_throttle_reasons.active` as documented in `nvidia-smi -h`.
+const NVIDIA_THROTTLE_REASONS: &[(u64, &str)] = &[
+    (0x1, "gpu_idle"),
+    (0x2, "applications_clocks_setting"),
+    (0x4, "sw_power_cap"),
+    (0x8, "hw_slowdown"),
+    (0x10, "sync_boost"),
+    (0x20, "sw_thermal_slowdown"),
+    (0x40, "hw_thermal_slowdown"),
+    (0x80, "hw_power_brake_slowdown"),
+    (0x100, "display_clock_setting"),
+];
+
+const PCI_VENDOR_AMD: &str = "0x1002";
+
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricsSource {
+    NvidiaSmi,
+    AmdSmi,
+    Sysfs,
+}
+
+#[derive(Serialize, Debug)]
+pub struct GpuMetrics {
+    pub index: Option<u32>,
+    pub pci_address: Option<String>,
+    pub source: MetricsSource,
+    pub utilization_percent: Option<f32>,
+    pub memory_used_mb: Option<u64>,
+    pub memory_total_mb: Option<u64>,
+    pub temperature_c: Option<f32>,
+    pub power_draw_w: Option<f32>,
+    pub power_limit_w: Option<f32>,
+    /// Reasons why the clocks are currently reduced; empty if they are not.
+    pub throttle_reasons: Vec<String>,
+    pub ecc_errors_corrected: Option<u64>,
+    pub ecc_errors_uncorrected: Option<u64>,
+}
+
+impl GpuMetrics {
+    fn new(source: MetricsSource) -> Self {
+        GpuMetrics {
+            index: None,
+            pci_address: None,
+            source,
+            utilization_percent: None,
+            memory_used_mb: None,
+            memory_total_mb: None,
+            temperature_c: None,
+            power_draw_w: None,
+            power_limit_w: None,
+            throttle_reasons: Vec::new(),
+            ecc_errors_corrected: None,
+            ecc_errors_uncorrected: None,
+        }
+    }
+}
+
+/// Samples the current utilization and health of every GPU. NVIDIA GPUs are
+/// read with `nvidia-smi`, AMD GPUs with `amd-smi` and from the amdgpu sysfs
+/// and hwmon files if `amd-smi` is not available.
+pub fn collect_gpu_metrics(settings: &ProbeSettings) -> Vec<GpuMetrics> {
+    info!("Start collecting GPU metrics");
+
+    let mut metrics = Vec::new();
+
+    if let Some(output) = probe::output(
+        "nvidia-smi",
+        &[
+            &format!("--query-gpu={NVIDIA_SMI_QUERY}"),
+            "--format=csv,noheader,nounits",
+        ],
+        settings,
+    ) {
+        metrics.extend(parse_nvidia_smi_query(&output));
+    }
+
+    let amd_metrics = probe::output("amd-smi", &["metric", "--json"], settings).map(|output| {
+        let bus_ids = probe::output("amd-smi", &["list", "--json"], settings)
+            .map(|list| parse_amd_smi_list(&list))
+            .unwrap_or_default();
+        parse_amd_smi_metric(&output, &bus_ids).unwrap_or_else(|e| {
+            warn!("Failed parsing amd-smi metrics: {e}");
+            Vec::new()
+        })
+    });
+    match amd_metrics {
+        Some(amd_metrics) => metrics.extend(amd_metrics),
+        None => metrics.extend(read_amdgpu_sysfs(Path::new("/sys/class/drm"))),
+    }
+
+    for gpu in &metrics {
+        info!(
+            "GPU {} ({:?}): {:?} % busy, {:?}/{:?} MB used, {:?} °C, {:?} W",
+            gpu.pci_address.as_deref().unwrap_or("unknown"),
+            gpu.source,
+            gpu.utilization_percent,
+            gpu.memory_used_mb,
+            gpu.memory_total_mb,
+            gpu.temperature_c,
+            gpu.power_draw_w
+        );
+        if gpu.ecc_errors_uncorrected.is_some_and(|count| count > 0) {
+            warn!(
+                "GPU {} reports uncorrected ECC errors",
+                gpu.pci_address.as_deref().unwrap_or("unknown")
+            );
+        }
+    }
+
+    info!("Finished collecting GPU metrics");
+    metrics
+}
+
+/// Parses `nvidia-smi --query-gpu=... --format=csv,noheader,nounits`, the
+/// columns are in the order of `NVIDIA_SMI_QUERY`.
+fn parse_nvidia_smi_query(output: &str) -> Vec<GpuMetrics> {
+    let mut metrics = Vec::new();
+
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+        if columns.len() != 12 {
+            warn!("Unexpected nvidia-smi query line: {line}");
+            continue;
+        }
+        let number = |idx: usize| {
+            columns[idx]
+                .parse::<f32>()
+                .ok()
+                .filter(|value| value.is_finite())
+        };
+        let count = |idx: usize| columns[idx].parse::<u64>().ok();
+
+        metrics.push(GpuMetrics {
+            index: columns[0].parse::<u32>().ok(),
+            pci_address: Some(normalize_bus_id(columns[1])),
+            utilization_percent: number(3),
+            memory_used_mb: count(4),
+            memory_total_mb: count(5),
+            temperature_c: number(6),
+            power_draw_w: number(7),
+            power_limit_w: number(8),
+            throttle_reasons: decode_throttle_reasons(columns[9]),
+            ecc_errors_corrected: count(10),
+            ecc_errors_uncorrected: count(11),
+            ..GpuMetrics::new(MetricsSource::NvidiaSmi)
+        });
+    }
+
+    metrics
+}
+
+/// nvidia-smi reports `00000000:17:00.0`, sysfs uses `0000:17:00.0`.
+fn normalize_bus_id(bus_id: &str) -> String {
+    let bus_id = bus_id.to_lowercase();
+    match bus_id.split_once(':') {
+        Some((domain, rest)) if domain.len() > 4 => {
+            format!("{}:{rest}", &domain[domain.len() - 4..])
+        }
+        _ => bus_id,
+    }
+}
+
+fn decode_throttle_reasons(mask: &str) -> Vec<String> {
+    let Ok(mask) = u64::from_str_radix(mask.trim_start_matches("0x"), 16) else {
+        return Vec::new();
+    };
+    NVIDIA_THROTTLE_REASONS
+        .iter()
+        // an idle GPU is not throttled
+        .filter(|(bit, name)| mask & bit != 0 && *name != "gpu_idle")
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+/// Maps the amd-smi GPU index to the PCI address from `amd-smi list --json`.
+fn parse_amd_smi_list(output: &str) -> HashMap<u64, String> {
+    let Ok(value) = serde_json::from_str::<Value>(output) else {
+        return HashMap::new();
+    };
+    amd_smi_gpu_entries(&value)
+        .iter()
+        .filter_map(|entry| {
+            let gpu = entry.get("gpu")?.as_u64()?;
+            let bdf = entry.get("bdf")?.as_str()?;
+            Some((gpu, bdf.to_lowercase()))
+        })
+        .collect()
+}
+
+/// Parses `amd-smi metric --json`. Releases differ in whether the list is
+/// wrapped in `gpu_data` and whether values are plain numbers or
+/// `{"value": .., "unit": ..}` objects, so both are accepted.
+fn parse_amd_smi_metric(
+    output: &str,
+    bus_ids: &HashMap<u64, String>,
+) -> Result<Vec<GpuMetrics>, serde_json::Error> {
+    let value = serde_json::from_str::<Value>(output)?;
+
+    let metrics = amd_smi_gpu_entries(&value)
+        .iter()
+        .map(|entry| {
+            let index = entry.get("gpu").and_then(Value::as_u64);
+            let field = |path: &[&str]| {
+                path.iter()
+                    .try_fold(entry, |value, key| value.get(key))
+                    .and_then(amd_smi_number)
+            };
+            let first = |paths: &[&[&str]]| paths.iter().find_map(|path| field(path));
+
+            let throttle_reasons = entry
+                .pointer("/power/throttle_status")
+                .and_then(Value::as_str)
+                .filter(|status| !matches!(*status, "UNTHROTTLED" | "N/A"))
+                .map(|status| vec![status.to_lowercase()])
+                .unwrap_or_default();
+
+            GpuMetrics {
+                index: index.map(|index| index as u32),
+                pci_address: index.and_then(|index| bus_ids.get(&index).cloned()),
+                utilization_percent: field(&["usage", "gfx_activity"]).map(|v| v as f32),
+                memory_used_mb: field(&["mem_usage", "used_vram"]).map(|v| v as u64),
+                memory_total_mb: field(&["mem_usage", "total_vram"]).map(|v| v as u64),
+                // MI300 parts have no edge sensor, only the junction temperature
+                temperature_c: first(&[&["temperature", "edge"], &["temperature", "hotspot"]])
+                    .map(|v| v as f32),
+                power_draw_w: first(&[
+                    &["power", "socket_power"],
+                    &["power", "average_socket_power"],
+                ])
+                .map(|v| v as f32),
+                power_limit_w: None,
+                throttle_reasons,
+                ecc_errors_corrected: first(&[
+                    &["ecc", "total_correctable_count"],
+                    &["ecc", "correctable_count"],
+                ])
+                .map(|v| v as u64),
+                ecc_errors_uncorrected: first(&[
+                    &["ecc", "total_uncorrectable_count"],
+                    &["ecc", "uncorrectable_count"],
+                ])
+                .map(|v| v as u64),
+                ..GpuMetrics::new(MetricsSource::AmdSmi)
+            }
+        })
+        .collect();
+
+    Ok(metrics)
+}
+
+fn amd_smi_gpu_entries(value: &Value) -> Vec<Value> {
+    let entries = value.get("gpu_data").unwrap_or(value);
+    match entries {
+        Value::Array(entries) => entries.to_owned(),
+        Value::Object(_) => vec![entries.to_owned()],
+        _ => Vec::new(),
+    }
+}
+
+fn amd_smi_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.split_whitespace().next()?.parse::<f64>().ok(),
+        Value::Object(_) => value.get("value").and_then(amd_smi_number),
+        _ => None,
+    }
+}
+
+/// Reads the metrics amdgpu exposes in sysfs for every AMD card.
+fn read_amdgpu_sysfs(drm_root: &Path) -> Vec<GpuMetrics> {
+    let Ok(entries) = fs::read_dir(drm_root) else {
+        return Vec::new();
+    };
+
+    let mut cards: Vec<_> = entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            // card0 is the device, card0-DP-1 one of its connectors
+            name.starts_with("card") && !name.contains('-')
+        })
+        .map(|entry| entry.path().join("device"))
+        .filter(|device| read_trimmed(&device.join("vendor")).as_deref() == Some(PCI_VENDOR_AMD))
+        .collect();
+    cards.sort();
+
+    cards
+        .iter()
+        .map(|device| {
+            let hwmon = fs::read_dir(device.join("hwmon"))
+                .ok()
+                .and_then(|mut entries| entries.next())
+                .and_then(Result::ok)
+                .map(|entry| entry.path());
+            let hwmon_value = |name: &str, scale: f32| {
+                hwmon
+                    .as_ref()
+                    .and_then(|hwmon| read_number(&hwmon.join(name)))
+                    .map(|value| value as f32 / scale)
+            };
+            let (ecc_errors_uncorrected, ecc_errors_corrected) =
+                read_trimmed(&device.join("ras/umc_err_count"))
+                    .map(|counts| parse_ras_counts(&counts))
+                    .unwrap_or_default();
+
+            GpuMetrics {
+                pci_address: fs::canonicalize(device)
+                    .ok()
+                    .and_then(|path| path.file_name().map(|n| n.to_string_lossy().to_string())),
+                utilization_percent: read_number(&device.join("gpu_busy_percent"))
+                    .map(|v| v as f32),
+                memory_used_mb: read_number(&device.join("mem_info_vram_used"))
+                    .map(|bytes| bytes / (1024 * 1024)),
+                memory_total_mb: read_number(&device.join("mem_info_vram_total"))
+                    .map(|bytes| bytes / (1024 * 1024)),
+                temperature_c: hwmon_value("temp1_input", 1000.0),
+                // newer kernels only provide the instantaneous power
+                power_draw_w: hwmon_value("power1_average", 1_000_000.0)
+                    .or_else(|| hwmon_value("power1_input", 1_000_000.0)),
+                power_limit_w: hwmon_value("power1_cap", 1_000_000.0),
+                ecc_errors_corrected,
+                ecc_errors_uncorrected,
+                ..GpuMetrics::new(MetricsSource::Sysfs)
+            }
+        })
+        .collect()
+}
+
+/// Parses `ras/umc_err_count`, which reads `ue: 0\nce: 2`.
+fn parse_ras_counts(counts: &str) -> (Option<u64>, Option<u64>) {
+    let count = |key: &str| {
+        counts.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            (name.trim() == key)
+                .then(|| value.trim().parse::<u64>().ok())
+                .flatten()
+        })
+    };
+    (count("ue"), count("ce"))
+}
+
+fn read_number(path: &Path) -> Option<u64> {
+    read_trimmed(path)?.parse::<u64>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nvidia_smi_query() {
+        let output = "\
+0, 00000000:17:00.0, NVIDIA H100 80GB HBM3, 87, 61440, 81559, 71, 512.34, 700.00, 0x0000000000000004, 0, 0
+1, 00000000:2A:00.0, NVIDIA H100 80GB HBM3, 0, 4, 81559, 33, 69.12, 700.00, 0x0000000000000001, 2, 1
+2, 00000000:3D:00.0, NVIDIA GeForce RTX 4090, 12, 512, 24564, 45, [N/A], [N/A], 0x0000000000000060, [N/A], [N/A]
+";
+        let metrics = parse_nvidia_smi_query(output);
+
+        assert_eq!(metrics.len(), 3);
+        let busy = &metrics[0];
+        assert_eq!(busy.pci_address.as_deref(), Some("0000:17:00.0"));
+        assert_eq!(busy.utilization_percent, Some(87.0));
+        assert_eq!(busy.memory_used_mb, Some(61440));
+        assert_eq!(busy.power_draw_w, Some(512.34));
+        assert_eq!(busy.throttle_reasons, vec!["sw_power_cap"]);
+
+        let idle = &metrics[1];
+        assert_eq!(idle.pci_address.as_deref(), Some("0000:2a:00.0"));
+        assert!(idle.throttle_reasons.is_empty());
+        assert_eq!(idle.ecc_errors_uncorrected, Some(1));
+
+        let consumer = &metrics[2];
+        assert_eq!(consumer.power_draw_w, None);
+        assert_eq!(consumer.ecc_errors_corrected, None);
+        assert_eq!(
+            consumer.throttle_reasons,
+            vec!["sw_thermal_slowdown", "hw_thermal_slowdown"]
+        );
+    }
+
+    #[test]
+    fn test_parse_amd_smi_metric() {
+        let list = r#"[{"gpu": 0, "bdf": "0000:0C:00.0", "uuid": "a1ff74a1-0000-1000-80e2-4b1a6d3c5e7f"}]"#;
+        let output = r#"{"gpu_data": [{
+            "gpu": 0,
+            "usage": {"gfx_activity": {"value": 42, "unit": "%"}, "umc_activity": {"value": 3, "unit": "%"}},
+            "power": {"socket_power": {"value": 402, "unit": "W"}, "throttle_status": "UNTHROTTLED"},
+            "temperature": {"edge": "N/A", "hotspot": {"value": 58, "unit": "C"}, "mem": {"value": 49, "unit": "C"}},
+            "ecc": {"total_correctable_count": 3, "total_uncorrectable_count": 0},
+            "mem_usage": {"total_vram": {"value": 196592, "unit": "MB"}, "used_vram": {"value": 283, "unit": "MB"}}
+        }]}"#;
+
+        let metrics = parse_amd_smi_metric(output, &parse_amd_smi_list(list)).unwrap();
+
+        assert_eq!(metrics.len(), 1);
+        let gpu = &metrics[0];
+        assert_eq!(gpu.pci_address.as_deref(), Some("0000:0c:00.0"));
+        assert_eq!(gpu.utilization_percent, Some(42.0));
+        assert_eq!(gpu.memory_used_mb, Some(283));
+        assert_eq!(gpu.memory_total_mb, Some(196592));
+        assert_eq!(gpu.temperature_c, Some(58.0));
+        assert_eq!(gpu.power_draw_w, Some(402.0));
+        assert!(gpu.throttle_reasons.is_empty());
+        assert_eq!(gpu.ecc_errors_corrected, Some(3));
+    }
+
+    #[test]
+    fn test_parse_amd_smi_metric_plain_values() {
+        let output = r#"[{
+            "gpu": 1,
+            "usage": {"gfx_activity": "100 %"},
+            "power": {"average_socket_power": 220, "throttle_status": "THROTTLED"},
+            "temperature": {"edge": 71, "hotspot": 80},
+            "ecc": {"correctable_count": 0, "uncorrectable_count": 0}
+        }]"#;
+
+        let metrics = parse_amd_smi_metric(output, &HashMap::new()).unwrap();
+
+        let gpu = &metrics[0];
+        assert_eq!(gpu.index, Some(1));
+        assert_eq!(gpu.pci_address, None);
+        assert_eq!(gpu.utilization_percent, Some(100.0));
+        assert_eq!(gpu.temperature_c, Some(71.0));
+        assert_eq!(gpu.power_draw_w, Some(220.0));
+        assert_eq!(gpu.throttle_reasons, vec!["throttled"]);
+        assert_eq!(gpu.memory_used_mb, None);
+    }
+
+    #[test]
+    fn test_read_amdgpu_sysfs() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = temp_dir.path();
+        let write = |path: &Path, content: &str| {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+
+        let device = root.join("devices/pci0000:00/0000:03:00.0");
+        write(&device.join("vendor"), "0x1002\n");
+        write(&device.join("gpu_busy_percent"), "17\n");
+        write(&device.join("mem_info_vram_used"), "1073741824\n");
+        write(&device.join("mem_info_vram_total"), "17163091968\n");
+        write(&device.join("ras/umc_err_count"), "ue: 1\nce: 5\n");
+        write(&device.join("hwmon/hwmon3/temp1_input"), "52000\n");
+        write(&device.join("hwmon/hwmon3/power1_input"), "87000000\n");
+        write(&device.join("hwmon/hwmon3/power1_cap"), "300000000\n");
+        let drm = root.join("class/drm");
+        fs::create_dir_all(drm.join("card0")).unwrap();
+        fs::create_dir_all(drm.join("card0-DP-1")).unwrap();
+        std::os::unix::fs::symlink(&device, drm.join("card0/device")).unwrap();
+
+        let metrics = read_amdgpu_sysfs(&drm);
+
+        assert_eq!(metrics.len(), 1);
+        let gpu = &metrics[0];
+        assert_eq!(gpu.pci_address.as_deref(), Some("0000:03:00.0"));
+        assert_eq!(gpu.utilization_percent, Some(17.0));
+        assert_eq!(gpu.memory_used_mb, Some(1024));
+        assert_eq!(gpu.memory_total_mb, Some(16368));
+        assert_eq!(gpu.temperature_c, Some(52.0));
+        assert_eq!(gpu.power_draw_w, Some(87.0));
+        assert_eq!(gpu.power_limit_w, Some(300.0));
+        assert_eq!(gpu.ecc_errors_corrected, Some(5));
+        assert_eq!(gpu.ecc_errors_uncorrected, Some(1));
+    }
+}
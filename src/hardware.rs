@@ -1,18 +1,33 @@
-use log::{error, info};
+use crate::config::Settings;
+use crate::dmi::{self, NodeDmi};
+use crate::interconnect::{self, GpuInterconnect};
+use crate::network::{self, NodeNetwork};
+use crate::storage::{self, NodeStorage};
+use crate::topology::{self, NodeTopology};
+use log::{error, info, warn};
 use pciid_parser::Database;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::Path;
-use std::{fs, io};
-use sysinfo::{Disks, System};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use sysinfo::System;
 
 const GPU_VRAM_TOML: &str =
     include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/assets/gpu_vram.toml"));
 
-pub fn collect_client_hardware() -> Result<NodeHardware, Box<dyn std::error::Error>> {
+/// Collects the hardware of the node. `sys` is shared with the metrics
+/// collector and only locked while it is refreshed and read.
+pub fn collect_client_hardware(
+    settings: &Settings,
+    sys: &Mutex<System>,
+) -> Result<NodeHardware, Box<dyn std::error::Error>> {
     info!("Start collecting hardware information");
-    let mut sys = System::new_all();
-    sys.refresh_all();
+    let (total_memory, cpu_count) = {
+        let mut sys = sys.lock().unwrap_or_else(PoisonError::into_inner);
+        sys.refresh_all();
+        (sys.total_memory(), sys.cpus().len())
+    };
 
     let mut node_hardware = NodeHardware {
         gpu_count: 0,
@@ -22,41 +37,28 @@ pub fn collect_client_hardware() -> Result<NodeHardware, Box<dyn std::error::Err
         cpu_cores: 0,
         memory_gb: 0,
         storage_gb: 0,
+        gpus: Vec::new(),
+        topology: NodeTopology::default(),
+        interconnect: None,
+        storage: NodeStorage::default(),
+        network: NodeNetwork::default(),
+        dmi: NodeDmi::default(),
     };
-    node_hardware.memory_gb = bytes_to_gib(sys.total_memory());
+    node_hardware.memory_gb = bytes_to_gib(total_memory);
     info!("Total memory: {} GiB", node_hardware.memory_gb);
 
-    node_hardware.cpu_cores = sys.cpus().len() as u64;
+    node_hardware.cpu_cores = cpu_count as u64;
     info!("Total number of CPU cores: {}", node_hardware.cpu_cores);
 
-    let disks = Disks::new_with_refreshed_list();
-    if let Some(disk) = disks
-        .iter()
-        .find(|disk| disk.mount_point() == Path::new("/"))
-    {
-        node_hardware.storage_gb = bytes_to_gb(disk.total_space());
-        info!(
-            "Root disk with filesystem {} and {} GB storage",
-            disk.file_system().to_string_lossy(),
-            node_hardware.storage_gb
-        );
-    }
+    node_hardware.dmi = dmi::collect_dmi();
 
-    let ethernet_connections = list_ethernet_connections().unwrap();
+    node_hardware.storage = storage::collect_storage(&settings.storage);
+    node_hardware.storage_gb = bytes_to_gb(node_hardware.storage.job_storage_bytes);
+    info!("Job storage: {} GB", node_hardware.storage_gb);
 
-    for (idx, ethernet_connection) in ethernet_connections.iter().enumerate() {
-        info!(
-            "Ethernet connection {} with name {} and speed of {} Mbps",
-            idx, ethernet_connection.0, ethernet_connection.1
-        );
-    }
+    node_hardware.network = network::collect_network();
 
-    let gpus = match list_pci_gpus() {
-        Ok(gpus) => gpus,
-        Err(e) => {
-            return Err(e);
-        }
-    };
+    let gpus = list_pci_gpus()?;
 
     if !gpus.is_empty() {
         node_hardware.gpu_count = gpus.len() as u8;
@@ -67,100 +69,72 @@ pub fn collect_client_hardware() -> Result<NodeHardware, Box<dyn std::error::Err
 
     info!("List GPUs:");
     for (idx, gpu) in gpus.iter().enumerate() {
-        info!("GPU {idx}: {} {} {} GB", gpu.vendor, gpu.gpu_type, gpu.vram);
+        info!(
+            "GPU {idx}: {} {} {} GB at {}",
+            gpu.vendor, gpu.gpu_type, gpu.vram, gpu.pci_address
+        );
+        if let Some(link) = &gpu.pcie {
+            info!(
+                "GPU {idx}: PCIe link {:?} GT/s x{:?} (max {:?} GT/s x{:?})",
+                link.current_speed_gts, link.current_width, link.max_speed_gts, link.max_width
+            );
+        }
+        if gpu.pcie_degraded {
+            warn!(
+                "GPU {idx} at {} runs on a degraded PCIe link",
+                gpu.pci_address
+            );
+        }
     }
 
+    let gpu_pci_addresses: Vec<String> =
+        gpus.iter().map(|gpu| gpu.pci_address.to_owned()).collect();
+    node_hardware.topology = topology::collect_topology(&gpu_pci_addresses);
+    node_hardware.interconnect = interconnect::collect_interconnect(&gpus, &settings.probes);
+    node_hardware.gpus = gpus;
+
     info!("Finished collecting hardware information");
     Ok(node_hardware)
 }
 
-fn list_ethernet_connections() -> io::Result<Vec<(String, i32)>> {
-    let items = fs::read_dir("/sys/class/net")?
-        .filter_map(|entry| {
-            let entry = entry.ok()?; // DirEntry oder skip
-            let name = entry.file_name().into_string().ok()?;
-            if !name.contains("en") {
-                return None;
-            }
-
-            let ty = fs::read_to_string(entry.path().join("type"))
-                .ok()?
-                .trim()
-                .parse::<i32>()
-                .ok()?;
-            if ty != 1 {
-                return None;
-            }
-
-            let speed = fs::read_to_string(entry.path().join("speed"))
-                .ok()
-                .and_then(|s| s.trim().parse::<i32>().ok())
-                .unwrap_or(-1);
-
-            Some((name, speed))
-        })
-        .collect::<Vec<(String, i32)>>(); // <— wichtig
-
-    Ok(items)
-}
-
 fn list_pci_gpus() -> Result<Vec<Gpu>, Box<dyn std::error::Error>> {
     let mut all_gpus = Vec::new();
 
-    let pci_db = Database::get_online().unwrap_or_else(|e| {
-        error!("Failed fetching online PCI database: {e}");
-        info!("Falling back to offline database");
-        Database::read().unwrap()
-    });
+    let pci_db = match Database::get_online() {
+        Ok(pci_db) => pci_db,
+        Err(e) => {
+            error!("Failed fetching online PCI database: {e}");
+            info!("Falling back to offline database");
+            Database::read().map_err(|e| format!("failed reading offline PCI database: {e}"))?
+        }
+    };
 
     let gpu_vram_map = load_gpu_vram_map_from_str(GPU_VRAM_TOML)?;
 
     for entry in fs::read_dir("/sys/bus/pci/devices/")? {
         let pci_entry = entry?;
-        let vendor_id = pci_entry.path().join("vendor");
-        let device_id = pci_entry.path().join("device");
-        let class_code = pci_entry.path().join("class");
-
-        let class = fs::read_to_string(&class_code)
-            .map_err(|e| {
-                error!("Failed reading the GPU class {e}");
-                e
-            })?
-            .trim()
-            .to_string();
+
+        // a single unreadable device must not hide the remaining GPUs
+        let class = match read_pci_attribute(&pci_entry.path(), "class") {
+            Ok(class) => class,
+            Err(e) => {
+                warn!("Skipping PCI device {:?}: {e}", pci_entry.file_name());
+                continue;
+            }
+        };
 
         if !(class.starts_with("0x03") || class.starts_with("0x12") || class.starts_with("0x0302"))
         {
             continue;
         }
 
-        let vendor_string = fs::read_to_string(&vendor_id)
-            .map_err(|e| {
-                error!("Failed reading the GPU vendor {e}");
-                e
-            })?
-            .trim()
-            .to_string();
-
-        let vendor_hex =
-            u16::from_str_radix(vendor_string.trim_start_matches("0x"), 16).map_err(|e| {
-                error!("Invalid vendor ID {vendor_string}: {e}");
-                e
-            })?;
-
-        let device_string = fs::read_to_string(&device_id)
-            .map_err(|e| {
-                error!("Failed reading the GPU device {e}");
-                e
-            })?
-            .trim()
-            .to_string();
-
-        let device_hex =
-            u16::from_str_radix(device_string.trim_start_matches("0x"), 16).map_err(|e| {
-                error!("Invalid vendor ID {device_string}: {e}");
-                e
-            })?;
+        let (vendor_hex, device_hex, device_string) = match read_pci_ids(&pci_entry.path()) {
+            Ok(ids) => ids,
+            Err(e) => {
+                warn!("Skipping PCI device {:?}: {e}", pci_entry.file_name());
+                continue;
+            }
+        };
 
         let pci_vendor = pci_db.vendors.get(&vendor_hex);
         let gpu_device = pci_vendor.and_then(|v| v.devices.get(&device_hex));
@@ -169,10 +143,19 @@ fn list_pci_gpus() -> Result<Vec<Gpu>, Box<dyn std::error::Error>> {
         let gpu_vram = gpu_vram_map.get(&device_string);
 
         if let (Some(vendor), Some(device)) = (vendor_mapped, gpu_device) {
+            let pcie = read_pcie_link(&pci_entry.path());
+            let pcie_bridge = upstream_bridge_dir(&pci_entry.path())
+                .and_then(|bridge_dir| read_pcie_link(&bridge_dir));
+            let pcie_degraded = pcie.as_ref().is_some_and(PcieLink::is_degraded);
+
             all_gpus.push(Gpu {
+                pci_address: pci_entry.file_name().to_string_lossy().to_string(),
                 vendor,
                 gpu_type: device.name.to_owned(),
                 vram: *gpu_vram.unwrap_or(&0),
+                pcie,
+                pcie_bridge,
+                pcie_degraded,
             });
         }
     }
@@ -180,10 +163,55 @@ fn list_pci_gpus() -> Result<Vec<Gpu>, Box<dyn std::error::Error>> {
     Ok(all_gpus)
 }
 
-struct Gpu {
-    vendor: String,
-    gpu_type: String,
-    vram: u64,
+fn read_pci_attribute(device_dir: &Path, attribute: &str) -> Result<String, String> {
+    fs::read_to_string(device_dir.join(attribute))
+        .map(|value| value.trim().to_string())
+        .map_err(|e| format!("failed reading the {attribute}: {e}"))
+}
+
+/// Reads the vendor and device id of a PCI device, the device id is also
+/// returned as written in sysfs for the VRAM lookup.
+fn read_pci_ids(device_dir: &Path) -> Result<(u16, u16, String), String> {
+    let vendor_string = read_pci_attribute(device_dir, "vendor")?;
+    let vendor_hex = u16::from_str_radix(vendor_string.trim_start_matches("0x"), 16)
+        .map_err(|e| format!("invalid vendor ID {vendor_string}: {e}"))?;
+
+    let device_string = read_pci_attribute(device_dir, "device")?;
+    let device_hex = u16::from_str_radix(device_string.trim_start_matches("0x"), 16)
+        .map_err(|e| format!("invalid device ID {device_string}: {e}"))?;
+
+    Ok((vendor_hex, device_hex, device_string))
+}
+
+#[derive(Serialize, Debug)]
+pub struct Gpu {
+    pub pci_address: String,
+    pub vendor: String,
+    pub gpu_type: String,
+    pub vram: u64,
+    pub pcie: Option<PcieLink>,
+    pub pcie_bridge: Option<PcieLink>,
+    pub pcie_degraded: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PcieLink {
+    pub pci_address: String,
+    pub current_speed_gts: Option<f32>,
+    pub current_width: Option<u8>,
+    pub max_speed_gts: Option<f32>,
+    pub max_width: Option<u8>,
+}
+
+impl PcieLink {
+    /// Only the width is compared: GPUs routinely drop the link speed while
+    /// idle, whereas a narrower link (e.g. x4 on a riser) stays narrow.
+    fn is_degraded(&self) -> bool {
+        matches!(
+            (self.current_width, self.max_width),
+            (Some(current), Some(max)) if current < max
+        )
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -195,6 +223,80 @@ pub struct NodeHardware {
     pub cpu_cores: u64,
     pub memory_gb: u64,
     pub storage_gb: u64,
+    pub gpus: Vec<Gpu>,
+    pub topology: NodeTopology,
+    pub interconnect: Option<GpuInterconnect>,
+    pub storage: NodeStorage,
+    pub network: NodeNetwork,
+    pub dmi: NodeDmi,
+}
+
+/// Returns human readable warnings about the collected hardware that the
+/// server should surface, e.g. GPUs running on a degraded PCIe link.
+pub fn hardware_warnings(node_hardware: &NodeHardware) -> Vec<String> {
+    node_hardware
+        .gpus
+        .iter()
+        .filter(|gpu| gpu.pcie_degraded)
+        .filter_map(|gpu| {
+            let link = gpu.pcie.as_ref()?;
+            Some(format!(
+                "GPU {} ({}) runs at PCIe x{} instead of x{}",
+                gpu.pci_address,
+                gpu.gpu_type,
+                link.current_width.unwrap_or(0),
+                link.max_width.unwrap_or(0)
+            ))
+        })
+        .collect()
+}
+
+fn read_pcie_link(device_dir: &Path) -> Option<PcieLink> {
+    let read = |name: &str| {
+        fs::read_to_string(device_dir.join(name))
+            .ok()
+            .map(|s| s.trim().to_string())
+    };
+
+    let current_speed = read("current_link_speed");
+    let current_width = read("current_link_width");
+    let max_speed = read("max_link_speed");
+    let max_width = read("max_link_width");
+
+    // devices that are not PCIe (or virtual functions) expose none of these
+    if current_speed.is_none()
+        && current_width.is_none()
+        && max_speed.is_none()
+        && max_width.is_none()
+    {
+        return None;
+    }
+
+    let pci_address = fs::canonicalize(device_dir)
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+        .unwrap_or_default();
+
+    Some(PcieLink {
+        pci_address,
+        current_speed_gts: current_speed.as_deref().and_then(parse_link_speed),
+        current_width: current_width.and_then(|w| w.parse::<u8>().ok()),
+        max_speed_gts: max_speed.as_deref().and_then(parse_link_speed),
+        max_width: max_width.and_then(|w| w.parse::<u8>().ok()),
+    })
+}
+
+fn upstream_bridge_dir(device_dir: &Path) -> Option<PathBuf> {
+    let parent = fs::canonicalize(device_dir).ok()?.parent()?.to_path_buf();
+    parent
+        .file_name()
+        .is_some_and(|n| topology::is_pci_address(&n.to_string_lossy()))
+        .then_some(parent)
+}
+
+/// Parses sysfs link speeds such as `16.0 GT/s PCIe` or `8 GT/s`.
+fn parse_link_speed(speed: &str) -> Option<f32> {
+    speed.split_whitespace().next()?.parse::<f32>().ok()
 }
 
 fn bytes_to_gb(bytes: u64) -> u64 {
@@ -233,3 +335,46 @@ struct VendorVRAMMap {
     amd: Option<HashMap<String, u64>>,
     nvidia: Option<HashMap<String, u64>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_link_speed() {
+        assert_eq!(parse_link_speed("16.0 GT/s PCIe"), Some(16.0));
+        assert_eq!(parse_link_speed("8 GT/s"), Some(8.0));
+        assert_eq!(parse_link_speed("Unknown"), None);
+    }
+
+    #[test]
+    fn test_read_pcie_link_detects_narrow_link() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let bridge = temp_dir.path().join("pci0000:00/0000:00:01.0");
+        let gpu = bridge.join("0000:01:00.0");
+        fs::create_dir_all(&gpu).unwrap();
+
+        for (dir, speed, width) in [
+            (&bridge, "16.0 GT/s PCIe", "16"),
+            (&gpu, "8.0 GT/s PCIe", "4"),
+        ] {
+            fs::write(dir.join("current_link_speed"), format!("{speed}\n")).unwrap();
+            fs::write(dir.join("current_link_width"), format!("{width}\n")).unwrap();
+            fs::write(dir.join("max_link_speed"), "16.0 GT/s PCIe\n").unwrap();
+            fs::write(dir.join("max_link_width"), "16\n").unwrap();
+        }
+
+        let link = read_pcie_link(&gpu).expect("GPU should expose a PCIe link");
+        assert_eq!(link.pci_address, "0000:01:00.0");
+        assert_eq!(link.current_speed_gts, Some(8.0));
+        assert_eq!(link.current_width, Some(4));
+        assert_eq!(link.max_width, Some(16));
+        assert!(link.is_degraded());
+
+        let bridge_link = upstream_bridge_dir(&gpu)
+            .and_then(|dir| read_pcie_link(&dir))
+            .expect("bridge should expose a PCIe link");
+        assert_eq!(bridge_link.pci_address, "0000:00:01.0");
+        assert!(!bridge_link.is_degraded());
+    }
+}
@@ -0,0 +1,431 @@
+use crate::collector::Inventory;
+use crate::config::{self, HealthSettings};
+use crate::hardware::Gpu;
+use crate::storage::NodeStorage;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+const REGISTRATION_FILE: &str = "registration.json";
+const DOCKER_PING_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Serialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Skip,
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl HealthStatus {
+    fn label(self) -> &'static str {
+        match self {
+            HealthStatus::Skip => "SKIP",
+            HealthStatus::Pass => "PASS",
+            HealthStatus::Warn => "WARN",
+            HealthStatus::Fail => "FAIL",
+        }
+    }
+}
+
+/// Summarized verdict whether the node is fit to run jobs.
+#[derive(Serialize, Debug)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub checks: Vec<HealthCheck>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HealthCheck {
+    pub name: &'static str,
+    pub status: HealthStatus,
+    pub message: String,
+}
+
+impl HealthCheck {
+    fn new(name: &'static str, status: HealthStatus, message: impl Into<String>) -> Self {
+        HealthCheck {
+            name,
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl HealthReport {
+    /// Prints one line per check, as shown by the `health` command.
+    pub fn print(&self) {
+        for check in &self.checks {
+            println!(
+                "{:<4}  {:<12}  {}",
+                check.status.label(),
+                check.name,
+                check.message
+            );
+        }
+        println!("Overall: {}", self.status.label());
+    }
+}
+
+/// What the node looked like when it registered, kept to compare later runs.
+#[derive(Serialize, Deserialize, Debug)]
+struct Registration {
+    gpu_count: u8,
+}
+
+pub(crate) fn store_registered_gpu_count(gpu_count: u8) -> Result<(), Box<dyn std::error::Error>> {
+    let path = config::state_dir_path()?.join(REGISTRATION_FILE);
+    fs::write(&path, serde_json::to_string(&Registration { gpu_count })?)?;
+    info!("Stored registered GPU count in {}", path.display());
+    Ok(())
+}
+
+fn registered_gpu_count() -> Option<u8> {
+    let path = config::state_dir_path().ok()?.join(REGISTRATION_FILE);
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str::<Registration>(&content)
+        .ok()
+        .map(|registration| registration.gpu_count)
+}
+
+pub(crate) fn evaluate(inventory: &Inventory, settings: &HealthSettings) -> HealthReport {
+    info!("Start evaluating node health");
+
+    let mut checks = Vec::new();
+    match &inventory.hardware {
+        Some(hardware) => {
+            let expected = settings.expected_gpu_count.or_else(registered_gpu_count);
+            checks.push(check_gpu_count(hardware.gpus.len(), expected));
+            checks.push(check_pcie_width(&hardware.gpus));
+            checks.push(check_gpu_driver(
+                &hardware.gpus,
+                Path::new("/sys/bus/pci/devices"),
+            ));
+            checks.push(check_disk_free(&hardware.storage, settings));
+        }
+        None => {
+            checks.push(HealthCheck::new(
+                "hardware",
+                HealthStatus::Fail,
+                "hardware inventory is not available",
+            ));
+        }
+    }
+
+    let docker_installed = inventory
+        .software
+        .as_ref()
+        .is_some_and(|software| software.tools.contains_key("docker"));
+    checks.push(check_docker(&settings.docker_socket, docker_installed));
+    checks.push(check_clock(settings));
+
+    let status = overall_status(&checks);
+    for check in checks.iter().filter(|c| c.status >= HealthStatus::Warn) {
+        warn!(
+            "Health check {} {:?}: {}",
+            check.name, check.status, check.message
+        );
+    }
+    info!("Finished evaluating node health: {status:?}");
+
+    HealthReport { status, checks }
+}
+
+fn overall_status(checks: &[HealthCheck]) -> HealthStatus {
+    checks
+        .iter()
+        .map(|check| check.status)
+        .max()
+        .filter(|status| *status != HealthStatus::Skip)
+        .unwrap_or(HealthStatus::Pass)
+}
+
+fn check_gpu_count(found: usize, expected: Option<u8>) -> HealthCheck {
+    let Some(expected) = expected else {
+        return HealthCheck::new(
+            "gpu_count",
+            HealthStatus::Skip,
+            format!("found {found} GPUs, no expected count known"),
+        );
+    };
+
+    if found == expected as usize {
+        HealthCheck::new(
+            "gpu_count",
+            HealthStatus::Pass,
+            format!("found {found} GPUs as expected"),
+        )
+    } else {
+        HealthCheck::new(
+            "gpu_count",
+            HealthStatus::Fail,
+            format!("found {found} GPUs, expected {expected}"),
+        )
+    }
+}
+
+fn check_pcie_width(gpus: &[Gpu]) -> HealthCheck {
+    let degraded: Vec<&str> = gpus
+        .iter()
+        .filter(|gpu| gpu.pcie_degraded)
+        .map(|gpu| gpu.pci_address.as_str())
+        .collect();
+
+    if gpus.is_empty() {
+        HealthCheck::new("pcie_width", HealthStatus::Skip, "no GPUs found")
+    } else if degraded.is_empty() {
+        HealthCheck::new(
+            "pcie_width",
+            HealthStatus::Pass,
+            "all GPUs run at full PCIe width",
+        )
+    } else {
+        HealthCheck::new(
+            "pcie_width",
+            HealthStatus::Warn,
+            format!("degraded PCIe link on {}", degraded.join(", ")),
+        )
+    }
+}
+
+fn check_gpu_driver(gpus: &[Gpu], pci_root: &Path) -> HealthCheck {
+    let unbound: Vec<&str> = gpus
+        .iter()
+        .filter(|gpu| !pci_root.join(&gpu.pci_address).join("driver").exists())
+        .map(|gpu| gpu.pci_address.as_str())
+        .collect();
+
+    if gpus.is_empty() {
+        HealthCheck::new("gpu_driver", HealthStatus::Skip, "no GPUs found")
+    } else if unbound.is_empty() {
+        HealthCheck::new(
+            "gpu_driver",
+            HealthStatus::Pass,
+            "a driver is bound to every GPU",
+        )
+    } else {
+        HealthCheck::new(
+            "gpu_driver",
+            HealthStatus::Fail,
+            format!("no driver bound to {}", unbound.join(", ")),
+        )
+    }
+}
+
+fn check_disk_free(storage: &NodeStorage, settings: &HealthSettings) -> HealthCheck {
+    let mut worst: Option<(f32, &str)> = None;
+    for filesystem in storage.filesystems.iter().filter(|fs| fs.job_storage) {
+        if filesystem.total_bytes == 0 {
+            continue;
+        }
+        let free_percent =
+            (filesystem.available_bytes as f64 * 100.0 / filesystem.total_bytes as f64) as f32;
+        if worst.is_none_or(|(lowest, _)| free_percent < lowest) {
+            worst = Some((free_percent, &filesystem.mount_point));
+        }
+    }
+
+    let Some((free_percent, mount_point)) = worst else {
+        return HealthCheck::new("disk_free", HealthStatus::Skip, "no job storage found");
+    };
+
+    let message = format!("{free_percent:.1} % free on {mount_point}");
+    let status = if free_percent < settings.min_free_disk_percent {
+        HealthStatus::Fail
+    } else if free_percent < settings.warn_free_disk_percent {
+        HealthStatus::Warn
+    } else {
+        HealthStatus::Pass
+    };
+    HealthCheck::new("disk_free", status, message)
+}
+
+fn check_docker(socket: &Path, docker_installed: bool) -> HealthCheck {
+    if !docker_installed && !socket.exists() {
+        return HealthCheck::new("docker", HealthStatus::Skip, "docker is not installed");
+    }
+
+    match ping_docker(socket) {
+        Ok(()) => HealthCheck::new("docker", HealthStatus::Pass, "docker daemon is reachable"),
+        Err(e) => HealthCheck::new(
+            "docker",
+            HealthStatus::Fail,
+            format!("docker daemon is not reachable: {e}"),
+        ),
+    }
+}
+
+/// Sends `GET /_ping` to the Docker API socket, which answers `OK`.
+fn ping_docker(socket: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(DOCKER_PING_TIMEOUT))?;
+    stream.set_write_timeout(Some(DOCKER_PING_TIMEOUT))?;
+    stream.write_all(b"GET /_ping HTTP/1.0\r\nHost: docker\r\n\r\n")?;
+
+    let mut response = String::new();
+    stream.take(4096).read_to_string(&mut response)?;
+    let status_line = response.lines().next().unwrap_or_default();
+    if !status_line.contains(" 200 ") {
+        return Err(format!("unexpected response {status_line:?}").into());
+    }
+    Ok(())
+}
+
+fn check_clock(settings: &HealthSettings) -> HealthCheck {
+    // modes = 0 only reads the kernel clock state and needs no privileges
+    let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+    let state = unsafe { libc::adjtimex(&mut timex) };
+    if state < 0 {
+        return HealthCheck::new(
+            "clock_sync",
+            HealthStatus::Skip,
+            format!(
+                "cannot read the clock state: {}",
+                std::io::Error::last_os_error()
+            ),
+        );
+    }
+    evaluate_clock_state(state, timex.status, timex.maxerror, settings)
+}
+
+fn evaluate_clock_state(
+    state: i32,
+    status: i32,
+    max_error_us: libc::c_long,
+    settings: &HealthSettings,
+) -> HealthCheck {
+    if state == libc::TIME_ERROR || status & libc::STA_UNSYNC != 0 {
+        return HealthCheck::new(
+            "clock_sync",
+            HealthStatus::Fail,
+            "system clock is not synchronized",
+        );
+    }
+
+    let max_error_ms = max_error_us / 1000;
+    if max_error_ms as u64 > settings.max_clock_error_ms {
+        HealthCheck::new(
+            "clock_sync",
+            HealthStatus::Warn,
+            format!("clock error may be up to {max_error_ms} ms"),
+        )
+    } else {
+        HealthCheck::new(
+            "clock_sync",
+            HealthStatus::Pass,
+            format!("clock is synchronized, maximum error {max_error_ms} ms"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Filesystem;
+    use std::os::unix::net::UnixListener;
+
+    fn filesystem(mount_point: &str, total_bytes: u64, available_bytes: u64) -> Filesystem {
+        Filesystem {
+            device: String::from("/dev/nvme0n1p2"),
+            mount_point: mount_point.to_string(),
+            file_system: String::from("ext4"),
+            total_bytes,
+            available_bytes,
+            job_storage: true,
+        }
+    }
+
+    #[test]
+    fn test_check_gpu_count() {
+        assert_eq!(check_gpu_count(8, Some(8)).status, HealthStatus::Pass);
+        assert_eq!(check_gpu_count(7, Some(8)).status, HealthStatus::Fail);
+        assert_eq!(check_gpu_count(7, None).status, HealthStatus::Skip);
+    }
+
+    #[test]
+    fn test_check_disk_free() {
+        let settings = HealthSettings::default();
+        let mut storage = NodeStorage {
+            filesystems: vec![
+                filesystem("/", 1000, 500),
+                filesystem("/scratch", 1000, 150),
+            ],
+            ..NodeStorage::default()
+        };
+
+        let check = check_disk_free(&storage, &settings);
+        assert_eq!(check.status, HealthStatus::Warn);
+        assert_eq!(check.message, "15.0 % free on /scratch");
+
+        storage.filesystems[1].available_bytes = 50;
+        assert_eq!(
+            check_disk_free(&storage, &settings).status,
+            HealthStatus::Fail
+        );
+
+        storage.filesystems.clear();
+        assert_eq!(
+            check_disk_free(&storage, &settings).status,
+            HealthStatus::Skip
+        );
+    }
+
+    #[test]
+    fn test_evaluate_clock_state() {
+        let settings = HealthSettings::default();
+
+        assert_eq!(
+            evaluate_clock_state(0, 0x2001, 12_000, &settings).status,
+            HealthStatus::Pass
+        );
+        assert_eq!(
+            evaluate_clock_state(0, 0x2001, 2_000_000, &settings).status,
+            HealthStatus::Warn
+        );
+        assert_eq!(
+            evaluate_clock_state(libc::TIME_ERROR, 0x0040, 16_000_000, &settings).status,
+            HealthStatus::Fail
+        );
+    }
+
+    #[test]
+    fn test_check_docker() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let socket = temp_dir.path().join("docker.sock");
+
+        assert_eq!(check_docker(&socket, false).status, HealthStatus::Skip);
+        assert_eq!(check_docker(&socket, true).status, HealthStatus::Fail);
+
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 256];
+            let _ = stream.read(&mut request).unwrap();
+            stream
+                .write_all(b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\nOK")
+                .unwrap();
+        });
+
+        assert_eq!(check_docker(&socket, true).status, HealthStatus::Pass);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_overall_status() {
+        let checks = [
+            HealthCheck::new("a", HealthStatus::Pass, ""),
+            HealthCheck::new("b", HealthStatus::Skip, ""),
+            HealthCheck::new("c", HealthStatus::Warn, ""),
+        ];
+        assert_eq!(overall_status(&checks), HealthStatus::Warn);
+        assert_eq!(
+            overall_status(&[HealthCheck::new("a", HealthStatus::Skip, "")]),
+            HealthStatus::Pass
+        );
+    }
+}
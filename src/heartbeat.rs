@@ -1,63 +1,730 @@
-use crate::hardware::NodeHardware;
-use crate::software::NodeSoftware;
-use crate::system::NodeSystem;
+use crate::collector::Inventory;
+use crate::config::{self, SpoolSettings};
+use crate::directives::ServerDirectives;
+use crate::hardware;
+use crate::health::HealthStatus;
+use crate::signing::{self, SigningKey};
+use crate::spool::{self, Spool};
 use log::{error, info, warn};
+use reqwest::StatusCode;
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
 
+const STATE_FILE: &str = "heartbeat_state.json";
+
+/// Inventory sections that rarely change. They are only sent when their hash
+/// differs from the last one the server acknowledged.
+const STATIC_SECTIONS: &[&str] = &["hardware", "software", "system", "custom"];
+
+/// Fields of the static sections that change on every run and are therefore
+/// left out of the hash. `*` stands for every element of an array.
+const VOLATILE_FIELDS: &[&[&str]] = &[
+    &["hardware", "gpus", "*", "pcie", "current_speed_gts"],
+    &["hardware", "gpus", "*", "pcie_bridge", "current_speed_gts"],
+    &["hardware", "storage", "filesystems", "*", "available_bytes"],
+    &["software", "probes"],
+    &["system", "uptime_secs"],
+];
+
+/// Hash of the static inventory the server confirmed to have stored.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct HeartbeatState {
+    acknowledged_inventory_hash: Option<String>,
+}
+
+/// The node's API and the client used to reach it. Heartbeats are signed
+/// with the node's key if it has one.
+pub(crate) struct NodeApi<'a> {
+    pub client: &'a Client,
+    pub api_url: &'a str,
+    pub node_id: &'a str,
+    pub signing_key: Option<&'a SigningKey>,
+}
+
+impl NodeApi<'_> {
+    pub(crate) fn endpoint(&self) -> String {
+        format!(
+            "{}/node/{}",
+            self.api_url.trim_end_matches("/"),
+            self.node_id
+        )
+    }
+}
+
+/// Sends the heartbeat. The static inventory is left out when the server
+/// already acknowledged the same `inventory_hash`; servers that do not echo
+/// the hash always receive the full inventory.
+///
+/// Heartbeats spooled during an outage are replayed first. `auth_token` is
+/// replaced by every token the server hands out, also when a later request
+/// fails. Returns the directives of the server's last response.
 pub(crate) fn send_heartbeat(
-    node_id: &str,
-    api_url: &str,
-    auth_token: &str,
-    node_hardware: &NodeHardware,
-    node_software: &NodeSoftware,
-    node_system: &NodeSystem,
-) -> Result<String, Box<dyn std::error::Error>> {
+    api: &NodeApi<'_>,
+    auth_token: &mut String,
+    inventory: &Inventory,
+    labels: &BTreeMap<String, String>,
+    spool_settings: &SpoolSettings,
+) -> Result<ServerDirectives, Box<dyn std::error::Error>> {
+    let state_path = config::state_dir_path()?.join(STATE_FILE);
+    let spool = Spool::open(spool_settings)?;
+    send_heartbeat_at(api, auth_token, inventory, labels, &state_path, &spool)
+}
+
+/// Replays the spooled heartbeats without sending a new one.
+pub(crate) fn flush_spool(
+    api: &NodeApi<'_>,
+    auth_token: &mut String,
+    spool_settings: &SpoolSettings,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let spool = Spool::open(spool_settings)?;
+    replay_spool(api, auth_token, &spool)
+}
+
+fn send_heartbeat_at(
+    api: &NodeApi<'_>,
+    auth_token: &mut String,
+    inventory: &Inventory,
+    labels: &BTreeMap<String, String>,
+    state_path: &Path,
+    spool: &Spool,
+) -> Result<ServerDirectives, Box<dyn std::error::Error>> {
     info!("Sending heartbeat");
-    let client = reqwest::blocking::Client::new();
-    let final_endpoint = format!("{}/node/{}", api_url.trim_end_matches("/"), node_id);
 
-    let payload = HeartbeatRequest {
-        hardware: node_hardware,
-        software: node_software,
-        system: node_system,
-    };
+    let warnings = inventory
+        .hardware
+        .as_ref()
+        .map(hardware::hardware_warnings)
+        .unwrap_or_default();
+    for warning in &warnings {
+        warn!("{warning}");
+    }
+
+    let inventory_hash = inventory_hash(&serde_json::to_value(inventory)?);
+    let payload = serde_json::to_value(HeartbeatRequest {
+        inventory,
+        labels,
+        warnings,
+        inventory_hash: &inventory_hash,
+    })?;
+
+    if let Err(e) = replay_spool(api, auth_token, spool) {
+        // keep this heartbeat behind the ones still waiting for delivery
+        return Err(spool_heartbeat(spool, &payload, inventory, e));
+    }
+
+    let mut state = load_state(state_path);
+    let mut full = state.acknowledged_inventory_hash.as_deref() != Some(inventory_hash.as_str());
+    // directives of a delivered delta heartbeat the server asked to resend in full
+    let mut delivered: Option<ServerDirectives> = None;
+
+    loop {
+        let body = if full {
+            info!("Sending full inventory with hash {inventory_hash}");
+            payload.clone()
+        } else {
+            info!("Static inventory unchanged, sending hash {inventory_hash} only");
+            without_static_sections(&payload)
+        };
+
+        let parsed = match (patch(api, auth_token, &body), delivered) {
+            (Ok(parsed), _) => parsed,
+            // the live sections already arrived, the cleared hash sends the
+            // full inventory with the next heartbeat
+            (Err(e), Some(directives)) => {
+                warn!("Failed sending the requested full inventory: {e}");
+                return Ok(directives);
+            }
+            (Err(e), None) if is_transient(e.as_ref()) => {
+                return Err(spool_heartbeat(spool, &payload, inventory, e));
+            }
+            (Err(e), None) => return Err(e),
+        };
+        info!("Successfully sent heartbeat and patched node hardware");
+
+        if full {
+            // only a server echoing the hash is known to accept heartbeats without the inventory
+            let acknowledged = parsed.inventory_hash.filter(|hash| *hash == inventory_hash);
+            if acknowledged.is_none() {
+                info!("Server did not acknowledge the inventory hash");
+            }
+            state.acknowledged_inventory_hash = acknowledged;
+            store_state(state_path, &state);
+            return Ok(parsed.directives);
+        }
+
+        if !parsed.full_inventory_required {
+            return Ok(parsed.directives);
+        }
+
+        info!("Server requested the full inventory");
+        state.acknowledged_inventory_hash = None;
+        store_state(state_path, &state);
+        full = true;
+        delivered = Some(parsed.directives);
+    }
+}
+
+/// PATCHes the node and takes over the rotated access token.
+fn patch(
+    api: &NodeApi<'_>,
+    auth_token: &mut String,
+    body: &Value,
+) -> Result<HeartbeatResponse, Box<dyn std::error::Error>> {
+    // the signature covers the exact bytes sent
+    let body = serde_json::to_vec(body)?;
+    let mut request = api
+        .client
+        .patch(api.endpoint())
+        .header(CONTENT_TYPE, "application/json")
+        .bearer_auth(&auth_token);
+    if let Some(key) = api.signing_key {
+        let signature = key.sign(&body)?;
+        request = request
+            .header(signing::TIMESTAMP_HEADER, signature.timestamp)
+            .header(signing::NONCE_HEADER, signature.nonce)
+            .header(signing::SIGNATURE_HEADER, signature.signature);
+    }
+    let resp = request.body(body).send()?;
+
+    if !resp.status().is_success() {
+        warn!(
+            "Heartbeat went through but server responded with status code {}",
+            resp.status()
+        );
+        return Err(Box::new(StatusError(resp.status())));
+    }
+
+    let parsed = resp.json::<HeartbeatResponse>().map_err(|e| {
+        error!("Failed parsing server response: {}", e);
+        e
+    })?;
+    auth_token.clone_from(&parsed.next_access_token);
+    Ok(parsed)
+}
+
+fn replay_spool(
+    api: &NodeApi<'_>,
+    auth_token: &mut String,
+    spool: &Spool,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    spool.replay(|payload| match patch(api, auth_token, &payload) {
+        Ok(_) => Ok(()),
+        Err(e) if is_rejected(e.as_ref()) => {
+            warn!("Server rejected spooled heartbeat, dropping it: {e}");
+            Ok(())
+        }
+        Err(e) => Err(e),
+    })
+}
+
+/// Queues the live sections of a heartbeat that could not be delivered. The
+/// static inventory is left out, the next delivered heartbeat carries it.
+/// Returns the delivery error, wrapped in `SpooledError` only if the spool
+/// kept the heartbeat.
+fn spool_heartbeat(
+    spool: &Spool,
+    payload: &Value,
+    inventory: &Inventory,
+    error: Box<dyn std::error::Error>,
+) -> Box<dyn std::error::Error> {
+    let mut payload = without_static_sections(payload);
+    if let Some(object) = payload.as_object_mut() {
+        object.remove("inventory_hash");
+        object.insert(String::from("spooled_at"), Value::from(spool::unix_time()));
+    }
+
+    let event = inventory.changes.as_ref().is_some_and(|c| !c.is_empty())
+        || inventory
+            .health
+            .as_ref()
+            .is_some_and(|h| h.status >= HealthStatus::Warn)
+        || inventory
+            .kernel_log
+            .as_ref()
+            .is_some_and(|k| !k.events.is_empty());
+
+    match spool.push(&payload, event) {
+        Ok(true) => Box::new(SpooledError(error)),
+        Ok(false) => error,
+        Err(e) => {
+            warn!("Failed to spool heartbeat: {e}");
+            error
+        }
+    }
+}
+
+/// Delivery failure of a heartbeat that waits in the spool for the next run.
+#[derive(Debug)]
+struct SpooledError(Box<dyn std::error::Error>);
+
+impl fmt::Display for SpooledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, spooled the heartbeat for later delivery", self.0)
+    }
+}
+
+impl std::error::Error for SpooledError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.0.as_ref())
+    }
+}
+
+/// Whether a failed heartbeat was spooled, so its contents count as reported.
+pub(crate) fn is_spooled(e: &(dyn std::error::Error + 'static)) -> bool {
+    e.is::<SpooledError>()
+}
+
+/// Server answer other than success to a heartbeat.
+#[derive(Debug)]
+struct StatusError(StatusCode);
 
-    let resp = client
-        .patch(final_endpoint)
-        .json(&payload)
-        .bearer_auth(auth_token)
-        .send();
-
-    match resp {
-        Ok(resp) => {
-            if resp.status().is_success() {
-                let parsed = resp.json::<HeartbeatResponse>().map_err(|e| {
-                    error!("Failed parsing server response: {}", e);
-                    e
-                })?;
-
-                info!("Successfully sent heartbeat and patched node hardware");
-                Ok(parsed.next_access_token)
-            } else {
-                warn!(
-                    "Heartbeat went through but server responded with status code {}",
-                    resp.status()
-                );
-                Err(format!("heartbeat failed with status {}", resp.status()).into())
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "heartbeat failed with status {}", self.0)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// Whether a later attempt may succeed: the API was unreachable, overloaded
+/// or failed internally.
+fn is_transient(e: &(dyn std::error::Error + 'static)) -> bool {
+    match e.downcast_ref::<StatusError>() {
+        Some(StatusError(status)) => {
+            status.is_server_error()
+                || *status == StatusCode::REQUEST_TIMEOUT
+                || *status == StatusCode::TOO_MANY_REQUESTS
+        }
+        None => e
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| !e.is_decode()),
+    }
+}
+
+/// Whether the server refused the heartbeat itself, so resending it is
+/// pointless. Authentication failures are not, a new token may fix them.
+fn is_rejected(e: &(dyn std::error::Error + 'static)) -> bool {
+    e.downcast_ref::<StatusError>()
+        .is_some_and(|StatusError(status)| {
+            status.is_client_error()
+                && ![
+                    StatusCode::UNAUTHORIZED,
+                    StatusCode::FORBIDDEN,
+                    StatusCode::REQUEST_TIMEOUT,
+                    StatusCode::TOO_MANY_REQUESTS,
+                ]
+                .contains(status)
+        })
+}
+
+/// SHA-256 over the static sections of the serialized inventory without their
+/// volatile fields. Object keys are sorted, so the hash is stable across runs.
+fn inventory_hash(inventory: &Value) -> String {
+    let mut sections = serde_json::Map::new();
+    for section in STATIC_SECTIONS {
+        let mut value = inventory.get(*section).cloned().unwrap_or(Value::Null);
+        for path in VOLATILE_FIELDS {
+            if path[0] == *section {
+                remove_field(&mut value, &path[1..]);
+            }
+        }
+        sections.insert(section.to_string(), value);
+    }
+    format!("{:x}", Sha256::digest(Value::Object(sections).to_string()))
+}
+
+fn remove_field(value: &mut Value, path: &[&str]) {
+    match path {
+        [] => {}
+        ["*", rest @ ..] => {
+            if let Some(items) = value.as_array_mut() {
+                items.iter_mut().for_each(|item| remove_field(item, rest));
             }
         }
-        Err(e) => Err(e.into()),
+        [key] => {
+            if let Some(object) = value.as_object_mut() {
+                object.remove(*key);
+            }
+        }
+        [key, rest @ ..] => {
+            if let Some(child) = value.get_mut(*key) {
+                remove_field(child, rest);
+            }
+        }
+    }
+}
+
+fn without_static_sections(payload: &Value) -> Value {
+    let mut payload = payload.clone();
+    if let Some(object) = payload.as_object_mut() {
+        for section in STATIC_SECTIONS {
+            object.remove(*section);
+        }
+    }
+    payload
+}
+
+fn load_state(path: &Path) -> HeartbeatState {
+    let Ok(content) = fs::read_to_string(path) else {
+        return HeartbeatState::default();
+    };
+    serde_json::from_str(&content).unwrap_or_else(|e| {
+        warn!(
+            "Ignoring unreadable heartbeat state {}: {e}",
+            path.display()
+        );
+        HeartbeatState::default()
+    })
+}
+
+fn store_state(path: &Path, state: &HeartbeatState) {
+    let content = serde_json::to_string(state).unwrap_or_default();
+    if let Err(e) = fs::write(path, content) {
+        // the next heartbeat then sends the full inventory again
+        warn!("Failed to store heartbeat state in {}: {e}", path.display());
     }
 }
 
 #[derive(Deserialize, Debug)]
 struct HeartbeatResponse {
     next_access_token: String,
+    /// Hash of the static inventory the server stored, absent on servers
+    /// without support for delta heartbeats.
+    #[serde(default)]
+    inventory_hash: Option<String>,
+    /// Set by the server when it lost or discarded the static inventory.
+    #[serde(default)]
+    full_inventory_required: bool,
+    #[serde(flatten)]
+    directives: ServerDirectives,
 }
 
 #[derive(Serialize)]
 struct HeartbeatRequest<'a> {
-    hardware: &'a NodeHardware,
-    software: &'a NodeSoftware,
-    system: &'a NodeSystem,
+    #[serde(flatten)]
+    inventory: &'a Inventory,
+    labels: &'a BTreeMap<String, String>,
+    warnings: Vec<String>,
+    inventory_hash: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::{Gpu, PcieLink};
+    use mockito::{Mock, Server, ServerGuard};
+
+    fn mock_heartbeat(server: &mut ServerGuard, full: bool, response: Value) -> Mock {
+        server
+            .mock("PATCH", "/node/node-123")
+            .match_request(move |request| {
+                let body: Value = serde_json::from_slice(request.body().unwrap()).unwrap();
+                body.get("inventory_hash").is_some() && body.get("hardware").is_some() == full
+            })
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(response.to_string())
+            .create()
+    }
+
+    fn heartbeat(
+        server: &ServerGuard,
+        inventory: &Inventory,
+        state_dir: &Path,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let spool = Spool::at(state_dir.join("spool"), &SpoolSettings::default())?;
+        let mut auth_token = String::from("token");
+        let api = NodeApi {
+            client: &Client::new(),
+            api_url: &server.url(),
+            node_id: "node-123",
+            signing_key: None,
+        };
+        send_heartbeat_at(
+            &api,
+            &mut auth_token,
+            inventory,
+            &BTreeMap::new(),
+            &state_dir.join(STATE_FILE),
+            &spool,
+        )?;
+        Ok(auth_token)
+    }
+
+    #[test]
+    fn test_delta_heartbeats() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut inventory = Inventory::mock();
+        let link = || PcieLink {
+            pci_address: String::from("0000:00:01.0"),
+            current_speed_gts: Some(16.0),
+            current_width: Some(16),
+            max_speed_gts: Some(16.0),
+            max_width: Some(16),
+        };
+        inventory.hardware.as_mut().unwrap().gpus.push(Gpu {
+            pci_address: String::from("0000:01:00.0"),
+            vendor: String::from("NVIDIA"),
+            gpu_type: String::from("H100"),
+            vram: 80,
+            pcie: Some(link()),
+            pcie_bridge: Some(link()),
+            pcie_degraded: false,
+        });
+        let hash = inventory_hash(&serde_json::to_value(&inventory).unwrap());
+        let mut server = Server::new();
+
+        let full = mock_heartbeat(
+            &mut server,
+            true,
+            serde_json::json!({ "next_access_token": "token-1", "inventory_hash": hash }),
+        );
+        assert_eq!(
+            heartbeat(&server, &inventory, temp_dir.path()).unwrap(),
+            "token-1"
+        );
+        full.assert();
+        full.remove();
+
+        // uptime and the link speed of idle GPUs are volatile and do not
+        // invalidate the acknowledged hash
+        inventory.system.as_mut().unwrap().uptime_secs += 900;
+        let gpu = &mut inventory.hardware.as_mut().unwrap().gpus[0];
+        gpu.pcie.as_mut().unwrap().current_speed_gts = Some(2.5);
+        gpu.pcie_bridge.as_mut().unwrap().current_speed_gts = Some(2.5);
+        let delta = mock_heartbeat(
+            &mut server,
+            false,
+            serde_json::json!({ "next_access_token": "token-2", "inventory_hash": hash }),
+        );
+        assert_eq!(
+            heartbeat(&server, &inventory, temp_dir.path()).unwrap(),
+            "token-2"
+        );
+        delta.assert();
+        delta.remove();
+
+        // a server that lost the inventory asks for it and gets it right away
+        let delta = mock_heartbeat(
+            &mut server,
+            false,
+            serde_json::json!({ "next_access_token": "token-3", "full_inventory_required": true }),
+        );
+        let full = mock_heartbeat(
+            &mut server,
+            true,
+            serde_json::json!({ "next_access_token": "token-4", "inventory_hash": hash }),
+        );
+        assert_eq!(
+            heartbeat(&server, &inventory, temp_dir.path()).unwrap(),
+            "token-4"
+        );
+        delta.assert();
+        delta.remove();
+        full.assert();
+        full.remove();
+
+        // a failed resend does not spool the live sections the server already
+        // has, the next heartbeat carries the full inventory
+        let delta = mock_heartbeat(
+            &mut server,
+            false,
+            serde_json::json!({ "next_access_token": "token-5", "full_inventory_required": true }),
+        );
+        let unavailable = server
+            .mock("PATCH", "/node/node-123")
+            .match_request(|request| {
+                let body: Value = serde_json::from_slice(request.body().unwrap()).unwrap();
+                body.get("hardware").is_some()
+            })
+            .with_status(503)
+            .create();
+        assert_eq!(
+            heartbeat(&server, &inventory, temp_dir.path()).unwrap(),
+            "token-5"
+        );
+        delta.assert();
+        delta.remove();
+        unavailable.assert();
+        unavailable.remove();
+        assert_eq!(
+            Spool::at(temp_dir.path().join("spool"), &SpoolSettings::default())
+                .unwrap()
+                .status()
+                .unwrap()
+                .entries,
+            0
+        );
+        let full = mock_heartbeat(
+            &mut server,
+            true,
+            serde_json::json!({ "next_access_token": "token-6", "inventory_hash": hash }),
+        );
+        assert_eq!(
+            heartbeat(&server, &inventory, temp_dir.path()).unwrap(),
+            "token-6"
+        );
+        full.assert();
+        full.remove();
+
+        // a hardware change sends the full inventory again
+        inventory.hardware.as_mut().unwrap().memory_gb = 128;
+        let full = mock_heartbeat(
+            &mut server,
+            true,
+            serde_json::json!({ "next_access_token": "token-7" }),
+        );
+        assert_eq!(
+            heartbeat(&server, &inventory, temp_dir.path()).unwrap(),
+            "token-7"
+        );
+        full.assert();
+        full.remove();
+    }
+
+    #[test]
+    fn test_server_without_delta_support() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let inventory = Inventory::mock();
+        let mut server = Server::new();
+
+        let full = mock_heartbeat(
+            &mut server,
+            true,
+            serde_json::json!({ "next_access_token": "token-1" }),
+        )
+        .expect(2);
+        heartbeat(&server, &inventory, temp_dir.path()).unwrap();
+        assert_eq!(
+            heartbeat(&server, &inventory, temp_dir.path()).unwrap(),
+            "token-1"
+        );
+        full.assert();
+        full.remove();
+        assert!(
+            load_state(&temp_dir.path().join(STATE_FILE))
+                .acknowledged_inventory_hash
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_spool_during_outage() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let inventory = Inventory::mock();
+        let mut server = Server::new();
+
+        let unavailable = server
+            .mock("PATCH", "/node/node-123")
+            .with_status(503)
+            .create();
+        let e = heartbeat(&server, &inventory, temp_dir.path()).unwrap_err();
+        assert!(is_spooled(e.as_ref()));
+        unavailable.assert();
+        unavailable.remove();
+
+        // the spooled heartbeat goes first and its token authorizes the next request
+        let replay = server
+            .mock("PATCH", "/node/node-123")
+            .match_header("authorization", "Bearer token")
+            .match_request(|request| {
+                let body: Value = serde_json::from_slice(request.body().unwrap()).unwrap();
+                body.get("spooled_at").is_some() && body.get("hardware").is_none()
+            })
+            .with_status(200)
+            .with_body(r#"{ "next_access_token": "token-1" }"#)
+            .create();
+        let current = server
+            .mock("PATCH", "/node/node-123")
+            .match_header("authorization", "Bearer token-1")
+            .with_status(200)
+            .with_body(r#"{ "next_access_token": "token-2" }"#)
+            .create();
+        assert_eq!(
+            heartbeat(&server, &inventory, temp_dir.path()).unwrap(),
+            "token-2"
+        );
+        replay.assert();
+        current.assert();
+    }
+
+    #[test]
+    fn test_disabled_spool() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let settings = SpoolSettings {
+            max_entries: 0,
+            ..SpoolSettings::default()
+        };
+        let spool = Spool::at(temp_dir.path().join("spool"), &settings).unwrap();
+        let inventory = Inventory::mock();
+        let payload = serde_json::to_value(&inventory).unwrap();
+
+        // a dropped heartbeat must not count as reported
+        let e = spool_heartbeat(&spool, &payload, &inventory, "unavailable".into());
+        assert!(!is_spooled(e.as_ref()));
+        assert_eq!(spool.status().unwrap().entries, 0);
+    }
+
+    #[test]
+    fn test_response_with_directives() {
+        let response: HeartbeatResponse = serde_json::from_value(serde_json::json!({
+            "next_access_token": "token-1",
+            "heartbeat_interval_minutes": 5,
+            "collectors": { "metrics": false },
+            "rollout": { "channel": "beta" }
+        }))
+        .unwrap();
+
+        assert_eq!(response.next_access_token, "token-1");
+        assert!(!response.full_inventory_required);
+        assert_eq!(response.directives.heartbeat_interval_minutes, Some(5));
+        assert_eq!(response.directives.collectors.get("metrics"), Some(&false));
+        assert!(response.directives.log_level.is_none());
+    }
+
+    #[test]
+    fn test_signed_heartbeat() {
+        let key = SigningKey::generate().unwrap();
+        let public_key = key.public_key();
+        let mut server = Server::new();
+        let mock = server
+            .mock("PATCH", "/node/node-123")
+            .match_request(move |request| {
+                let header = |name| request.header(name).first().unwrap().to_str().unwrap();
+                signing::verify(
+                    &public_key,
+                    header(signing::TIMESTAMP_HEADER),
+                    header(signing::NONCE_HEADER),
+                    header(signing::SIGNATURE_HEADER),
+                    request.body().unwrap(),
+                )
+            })
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"next_access_token": "token-1"}"#)
+            .create();
+
+        let api = NodeApi {
+            client: &Client::new(),
+            api_url: &server.url(),
+            node_id: "node-123",
+            signing_key: Some(&key),
+        };
+        let mut auth_token = String::from("token");
+        patch(
+            &api,
+            &mut auth_token,
+            &serde_json::json!({ "gpu_count": 8 }),
+        )
+        .unwrap();
+        mock.assert();
+        assert_eq!(auth_token, "token-1");
+    }
 }
@@ -0,0 +1,281 @@
+use crate::config::HttpSettings;
+use crate::mtls::ClientIdentity;
+use log::info;
+use reqwest::blocking::Client;
+use reqwest::{NoProxy, Proxy};
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, ring};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// User-Agent of every request, followed by the configured `user_agent`.
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Builds the client for all requests to the API. Without a configured
+/// proxy, `HTTPS_PROXY`, `HTTP_PROXY` and `NO_PROXY` are honored. With an
+/// identity, the client certificate is presented on every connection.
+pub(crate) fn build_client(
+    settings: &HttpSettings,
+    identity: Option<&ClientIdentity>,
+) -> Result<Client, Box<dyn std::error::Error>> {
+    let user_agent = match &settings.user_agent {
+        Some(suffix) => format!("{USER_AGENT} {suffix}"),
+        None => USER_AGENT.to_string(),
+    };
+
+    let mut builder = Client::builder()
+        .user_agent(user_agent)
+        .connect_timeout(Duration::from_secs(settings.connect_timeout_secs))
+        .timeout(Duration::from_secs(settings.timeout_secs))
+        .use_preconfigured_tls(tls_config(settings, identity)?);
+
+    if let Some(proxy) = &settings.proxy {
+        info!("Sending requests through proxy {proxy}");
+        let no_proxy = settings
+            .no_proxy
+            .as_deref()
+            .map_or_else(NoProxy::from_env, NoProxy::from_string);
+        builder = builder.proxy(Proxy::all(proxy)?.no_proxy(no_proxy));
+    }
+
+    Ok(builder.build()?)
+}
+
+fn tls_config(
+    settings: &HttpSettings,
+    identity: Option<&ClientIdentity>,
+) -> Result<ClientConfig, Box<dyn std::error::Error>> {
+    let provider = crypto_provider();
+
+    let mut roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    if let Some(path) = &settings.ca_bundle {
+        let mut added = 0;
+        for certificate in CertificateDer::pem_file_iter(path)? {
+            roots.add(certificate?)?;
+            added += 1;
+        }
+        if added == 0 {
+            return Err(format!("no certificates found in CA bundle {}", path.display()).into());
+        }
+        info!("Trusting {added} certificates from {}", path.display());
+    }
+
+    let mut verifier: Arc<dyn ServerCertVerifier> =
+        WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone()).build()?;
+    if !settings.pinned_certificates.is_empty() {
+        verifier = Arc::new(PinnedCertificateVerifier {
+            inner: verifier,
+            pins: settings
+                .pinned_certificates
+                .iter()
+                .map(|pin| normalize_fingerprint(pin))
+                .collect(),
+        });
+    }
+
+    // the custom verifier still runs the full WebPKI validation first
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
+
+    let Some(identity) = identity else {
+        return Ok(builder.with_no_client_auth());
+    };
+    let certificates = CertificateDer::pem_slice_iter(identity.certificate_pem.as_bytes())
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_slice(identity.key_pem.as_bytes())?;
+    Ok(builder.with_client_auth_cert(certificates, key)?)
+}
+
+/// Accepts the chain of the server only if one of its certificates has a
+/// pinned fingerprint.
+#[derive(Debug)]
+struct PinnedCertificateVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    pins: Vec<String>,
+}
+
+impl ServerCertVerifier for PinnedCertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .any(|certificate| {
+                let fingerprint = format!("{:x}", Sha256::digest(certificate.as_ref()));
+                self.pins.contains(&fingerprint)
+            });
+        if !pinned {
+            return Err(rustls::Error::General(String::from(
+                "server certificate does not match a pinned fingerprint",
+            )));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Accepts fingerprints as printed by `openssl x509 -fingerprint -sha256`.
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| *c != ':')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn crypto_provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::{ServerConfig, ServerConnection, StreamOwned};
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::thread;
+
+    const SERVER_FINGERPRINT: &str = "D3:6B:1C:46:9D:51:37:8B:A3:54:6F:55:2D:63:69:E9:D8:54:30:5C:B1:53:84:F7:E3:C3:C8:69:25:31:BE:AC";
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/tls")
+            .join(name)
+    }
+
+    /// HTTPS server with a certificate of the test CA answering `{}`,
+    /// optionally only to clients with a certificate of the test CA.
+    fn serve(client_auth: bool) -> u16 {
+        let certificates = CertificateDer::pem_file_iter(fixture("server.pem"))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let key = PrivateKeyDer::from_pem_file(fixture("server.key")).unwrap();
+        let builder = ServerConfig::builder_with_provider(crypto_provider())
+            .with_safe_default_protocol_versions()
+            .unwrap();
+        let builder = if client_auth {
+            let mut roots = RootCertStore::empty();
+            roots
+                .add(CertificateDer::from_pem_file(fixture("ca.pem")).unwrap())
+                .unwrap();
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), crypto_provider())
+                    .build()
+                    .unwrap();
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+        let config = Arc::new(builder.with_single_cert(certificates, key).unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let connection = ServerConnection::new(config.clone()).unwrap();
+                let mut tls = StreamOwned::new(connection, stream);
+                let mut request = [0; 4096];
+                if tls.read(&mut request).is_ok() {
+                    let _ = tls.write_all(
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}",
+                    );
+                    tls.conn.send_close_notify();
+                    let _ = tls.flush();
+                }
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_ca_bundle_and_pinning() {
+        let port = serve(false);
+        let url = format!("https://localhost:{port}/");
+
+        // the test CA is unknown to the bundled roots
+        let client = build_client(&HttpSettings::default(), None).unwrap();
+        assert!(client.get(&url).send().is_err());
+
+        let mut settings = HttpSettings {
+            ca_bundle: Some(fixture("ca.pem")),
+            ..HttpSettings::default()
+        };
+        let client = build_client(&settings, None).unwrap();
+        assert!(client.get(&url).send().unwrap().status().is_success());
+
+        settings.pinned_certificates = vec![String::from(SERVER_FINGERPRINT)];
+        let client = build_client(&settings, None).unwrap();
+        assert!(client.get(&url).send().unwrap().status().is_success());
+
+        settings.pinned_certificates = vec!["00".repeat(32)];
+        let client = build_client(&settings, None).unwrap();
+        assert!(client.get(&url).send().is_err());
+    }
+
+    #[test]
+    fn test_client_certificate() {
+        let port = serve(true);
+        let url = format!("https://localhost:{port}/");
+        let settings = HttpSettings {
+            ca_bundle: Some(fixture("ca.pem")),
+            ..HttpSettings::default()
+        };
+
+        let client = build_client(&settings, None).unwrap();
+        assert!(client.get(&url).send().is_err());
+
+        let identity = ClientIdentity {
+            certificate_pem: fs::read_to_string(fixture("client.pem")).unwrap(),
+            key_pem: fs::read_to_string(fixture("client.key")).unwrap(),
+        };
+        let client = build_client(&settings, Some(&identity)).unwrap();
+        assert!(client.get(&url).send().unwrap().status().is_success());
+    }
+}
@@ -0,0 +1,404 @@
+use crate::config::ProbeSettings;
+use crate::hardware::Gpu;
+use crate::probe;
+use log::{info, warn};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// KFD io_link type for AMD xGMI links (`CRAT_IOLINK_TYPE_XGMI`).
+const KFD_IOLINK_TYPE_XGMI: u32 = 11;
+
+#[derive(Serialize, Debug)]
+pub struct GpuInterconnect {
+    pub source: String,
+    pub devices: Vec<String>,
+    pub links: Vec<GpuLink>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct GpuLink {
+    pub gpu_a: usize,
+    pub gpu_b: usize,
+    pub link_type: LinkType,
+    pub link_count: Option<u32>,
+    pub raw: String,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkType {
+    Nvlink,
+    Xgmi,
+    PcieSwitch,
+    HostBridge,
+    NumaNode,
+    System,
+    Pcie,
+    Unknown,
+}
+
+/// Derives the GPU-to-GPU interconnect matrix for multi-GPU nodes. Vendor
+/// tools are preferred; for AMD the KFD topology in sysfs is used as a
+/// fallback. Returns `None` if there is nothing to report.
+pub fn collect_interconnect(gpus: &[Gpu], settings: &ProbeSettings) -> Option<GpuInterconnect> {
+    if gpus.len() < 2 {
+        return None;
+    }
+
+    info!("Start collecting GPU interconnect topology");
+
+    let interconnect = if gpus.iter().any(|gpu| gpu.vendor == "NVIDIA") {
+        probe::output("nvidia-smi", &["topo", "-m"], settings)
+            .and_then(|out| parse_nvidia_smi_topo(&out))
+            .map(|(devices, links)| ("nvidia-smi", devices, links))
+    } else if gpus.iter().any(|gpu| gpu.vendor == "AMD") {
+        probe::output("amd-smi", &["topology"], settings)
+            .and_then(|out| parse_amd_smi_topology(&out))
+            .map(|(devices, links)| ("amd-smi", devices, links))
+            .or_else(|| {
+                parse_kfd_topology(Path::new("/sys/class/kfd/kfd/topology/nodes"))
+                    .map(|(devices, links)| ("sysfs", devices, links))
+            })
+    } else {
+        None
+    };
+
+    let Some((source, devices, links)) = interconnect else {
+        warn!("No source for the GPU interconnect topology available");
+        return None;
+    };
+
+    for link in &links {
+        info!(
+            "GPU {} <-> GPU {}: {:?} ({})",
+            link.gpu_a, link.gpu_b, link.link_type, link.raw
+        );
+    }
+
+    info!("Finished collecting GPU interconnect topology");
+    Some(GpuInterconnect {
+        source: source.to_string(),
+        devices,
+        links,
+    })
+}
+
+fn classify(raw: &str) -> (LinkType, Option<u32>) {
+    if let Some(count) = raw.strip_prefix("NV") {
+        return (LinkType::Nvlink, count.parse::<u32>().ok());
+    }
+    let link_type = match raw {
+        "XGMI" => LinkType::Xgmi,
+        "PIX" | "PXB" => LinkType::PcieSwitch,
+        "PHB" => LinkType::HostBridge,
+        "NODE" => LinkType::NumaNode,
+        "SYS" | "SOC" => LinkType::System,
+        "PCIE" => LinkType::Pcie,
+        _ => LinkType::Unknown,
+    };
+    (link_type, None)
+}
+
+fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // skip the CSI sequence up to and including its final letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Parses the matrix printed by `nvidia-smi topo -m`. Only the GPU rows and
+/// columns are kept; NIC columns and the affinity columns are ignored.
+fn parse_nvidia_smi_topo(output: &str) -> Option<(Vec<String>, Vec<GpuLink>)> {
+    let output = strip_ansi(output);
+    let mut lines = output.lines().filter(|l| !l.trim().is_empty());
+
+    let header = lines.next()?;
+    let devices: Vec<String> = header
+        .split('\t')
+        .map(str::trim)
+        .filter(|col| !col.is_empty())
+        .take_while(|col| col.starts_with("GPU") && !col.contains(' '))
+        .map(str::to_string)
+        .collect();
+    if devices.is_empty() {
+        return None;
+    }
+
+    let mut links = Vec::new();
+    for line in lines {
+        let mut cells = line.split('\t').map(str::trim);
+        let Some(row) = cells.next() else { continue };
+        let Some(gpu_a) = devices.iter().position(|d| d == row) else {
+            continue;
+        };
+
+        for (gpu_b, raw) in cells.take(devices.len()).enumerate() {
+            // the matrix is symmetric, keep every pair once
+            if gpu_b <= gpu_a {
+                continue;
+            }
+            let (link_type, link_count) = classify(raw);
+            links.push(GpuLink {
+                gpu_a,
+                gpu_b,
+                link_type,
+                link_count,
+                raw: raw.to_string(),
+            });
+        }
+    }
+
+    Some((devices, links))
+}
+
+/// Parses the `LINK TYPE TABLE` section of `amd-smi topology`.
+fn parse_amd_smi_topology(output: &str) -> Option<(Vec<String>, Vec<GpuLink>)> {
+    let mut lines = output
+        .lines()
+        .skip_while(|l| !l.trim_start().starts_with("LINK TYPE TABLE"))
+        .skip(1);
+
+    let devices: Vec<String> = lines
+        .next()?
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    if devices.is_empty() {
+        return None;
+    }
+
+    let mut links = Vec::new();
+    for line in lines.take_while(|l| !l.trim().is_empty()) {
+        let mut cells = line.split_whitespace();
+        let Some(row) = cells.next() else { continue };
+        let Some(gpu_a) = devices.iter().position(|d| d == row) else {
+            continue;
+        };
+
+        for (gpu_b, raw) in cells.take(devices.len()).enumerate() {
+            if gpu_b <= gpu_a {
+                continue;
+            }
+            let (link_type, link_count) = classify(raw);
+            links.push(GpuLink {
+                gpu_a,
+                gpu_b,
+                link_type,
+                link_count,
+                raw: raw.to_string(),
+            });
+        }
+    }
+
+    Some((devices, links))
+}
+
+/// Reads the KFD topology exposed by the amdgpu driver. GPU nodes are the ones
+/// with a non-zero `gpu_id`; their `io_links` describe the peer connections.
+fn parse_kfd_topology(nodes_root: &Path) -> Option<(Vec<String>, Vec<GpuLink>)> {
+    let mut gpu_nodes: Vec<u32> = fs::read_dir(nodes_root)
+        .ok()?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let node = entry.file_name().to_string_lossy().parse::<u32>().ok()?;
+            let gpu_id = fs::read_to_string(entry.path().join("gpu_id")).ok()?;
+            (gpu_id.trim() != "0").then_some(node)
+        })
+        .collect();
+    gpu_nodes.sort_unstable();
+    if gpu_nodes.len() < 2 {
+        return None;
+    }
+
+    let mut links = Vec::new();
+    for (gpu_a, node) in gpu_nodes.iter().enumerate() {
+        let Ok(io_links) = fs::read_dir(nodes_root.join(node.to_string()).join("io_links")) else {
+            continue;
+        };
+
+        for io_link in io_links.flatten() {
+            let Ok(properties) = fs::read_to_string(io_link.path().join("properties")) else {
+                continue;
+            };
+            let value = |key: &str| {
+                properties.lines().find_map(|line| {
+                    let (k, v) = line.split_once(' ')?;
+                    (k == key).then(|| v.trim().parse::<u32>().ok())?
+                })
+            };
+
+            let Some(gpu_b) =
+                value("node_to").and_then(|to| gpu_nodes.iter().position(|n| *n == to))
+            else {
+                continue;
+            };
+            if gpu_b <= gpu_a {
+                continue;
+            }
+
+            let link_type = if value("type") == Some(KFD_IOLINK_TYPE_XGMI) {
+                LinkType::Xgmi
+            } else {
+                LinkType::Pcie
+            };
+            links.push(GpuLink {
+                gpu_a,
+                gpu_b,
+                link_type,
+                link_count: None,
+                raw: format!("type {}", value("type").unwrap_or(0)),
+            });
+        }
+    }
+
+    links.sort_by_key(|link| (link.gpu_a, link.gpu_b));
+    let devices = gpu_nodes.iter().map(|n| format!("kfd node {n}")).collect();
+    Some((devices, links))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NVIDIA_SMI_TOPO_DGX: &str = "\t\u{1b}[4mGPU0\tGPU1\tGPU2\tGPU3\tNIC0\tCPU Affinity\tNUMA Affinity\tGPU NUMA ID\u{1b}[0m
+GPU0\t X \tNV12\tNV12\tNV12\tSYS\t0-63\t0\t\tN/A
+GPU1\tNV12\t X \tNV12\tNV12\tSYS\t0-63\t0\t\tN/A
+GPU2\tNV12\tNV12\t X \tNV12\tSYS\t64-127\t1\t\tN/A
+GPU3\tNV12\tNV12\tNV12\t X \tSYS\t64-127\t1\t\tN/A
+NIC0\tSYS\tSYS\tSYS\tSYS\t X \t\t\t\t
+
+Legend:
+
+  X    = Self
+  SYS  = Connection traversing PCIe as well as the SMP interconnect between NUMA nodes (e.g., QPI/UPI)
+  NV#  = Connection traversing a bonded set of # NVLinks
+
+NIC Legend:
+
+  NIC0: mlx5_0
+";
+
+    const NVIDIA_SMI_TOPO_PCIE: &str = "\tGPU0\tGPU1\tCPU Affinity\tNUMA Affinity
+GPU0\t X \tPHB\t0-15\t0
+GPU1\tPHB\t X \t0-15\t0
+
+Legend:
+
+  X    = Self
+  PHB  = Connection traversing PCIe as well as a PCIe Host Bridge (typically the CPU)
+";
+
+    const AMD_SMI_TOPOLOGY: &str = "ACCESS TABLE:
+             0000:0c:00.0 0000:22:00.0 0000:38:00.0
+0000:0c:00.0 ENABLED      ENABLED      ENABLED
+0000:22:00.0 ENABLED      ENABLED      ENABLED
+0000:38:00.0 ENABLED      ENABLED      ENABLED
+
+WEIGHT TABLE:
+             0000:0c:00.0 0000:22:00.0 0000:38:00.0
+0000:0c:00.0 0            15           15
+0000:22:00.0 15           0            15
+0000:38:00.0 15           15           0
+
+LINK TYPE TABLE:
+             0000:0c:00.0 0000:22:00.0 0000:38:00.0
+0000:0c:00.0 SELF         XGMI         XGMI
+0000:22:00.0 XGMI         SELF         PCIE
+0000:38:00.0 XGMI         PCIE         SELF
+
+NUMA BW TABLE:
+             0000:0c:00.0 0000:22:00.0 0000:38:00.0
+0000:0c:00.0 N/A          50000-50000  50000-50000
+";
+
+    #[test]
+    fn test_parse_nvidia_smi_topo_nvlink() {
+        let (devices, links) = parse_nvidia_smi_topo(NVIDIA_SMI_TOPO_DGX).unwrap();
+
+        assert_eq!(devices, vec!["GPU0", "GPU1", "GPU2", "GPU3"]);
+        assert_eq!(links.len(), 6);
+        assert!(links.iter().all(|l| l.link_type == LinkType::Nvlink));
+        assert!(links.iter().all(|l| l.link_count == Some(12)));
+        assert_eq!((links[5].gpu_a, links[5].gpu_b), (2, 3));
+    }
+
+    #[test]
+    fn test_parse_nvidia_smi_topo_pcie() {
+        let (devices, links) = parse_nvidia_smi_topo(NVIDIA_SMI_TOPO_PCIE).unwrap();
+
+        assert_eq!(devices.len(), 2);
+        assert_eq!(
+            links,
+            vec![GpuLink {
+                gpu_a: 0,
+                gpu_b: 1,
+                link_type: LinkType::HostBridge,
+                link_count: None,
+                raw: String::from("PHB"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_amd_smi_topology() {
+        let (devices, links) = parse_amd_smi_topology(AMD_SMI_TOPOLOGY).unwrap();
+
+        assert_eq!(devices[1], "0000:22:00.0");
+        let types: Vec<_> = links
+            .iter()
+            .map(|l| (l.gpu_a, l.gpu_b, l.link_type))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                (0, 1, LinkType::Xgmi),
+                (0, 2, LinkType::Xgmi),
+                (1, 2, LinkType::Pcie)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_without_matrix() {
+        assert!(parse_nvidia_smi_topo("").is_none());
+        assert!(parse_amd_smi_topology("ACCESS TABLE:\n").is_none());
+    }
+
+    #[test]
+    fn test_parse_kfd_topology() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let nodes = temp_dir.path();
+
+        for (node, gpu_id) in [(0, "0"), (1, "4660"), (2, "8170")] {
+            fs::create_dir_all(nodes.join(format!("{node}/io_links"))).unwrap();
+            fs::write(nodes.join(format!("{node}/gpu_id")), format!("{gpu_id}\n")).unwrap();
+        }
+        for (node, link, to, ty) in [(1, 0, 0, 2), (1, 1, 2, 11), (2, 0, 1, 11)] {
+            let dir = nodes.join(format!("{node}/io_links/{link}"));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(
+                dir.join("properties"),
+                format!("type {ty}\nversion_major 0\nnode_from {node}\nnode_to {to}\nweight 15\n"),
+            )
+            .unwrap();
+        }
+
+        let (devices, links) = parse_kfd_topology(nodes).unwrap();
+
+        assert_eq!(devices, vec!["kfd node 1", "kfd node 2"]);
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].link_type, LinkType::Xgmi);
+    }
+}
@@ -0,0 +1,420 @@
+use crate::config::{self, ProbeSettings};
+use crate::probe::{self, ProbeStatus};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use sysinfo::System;
+
+const CURSOR_FILE: &str = "kernel_log_cursor.json";
+const KMSG_RECORD_SIZE: usize = 8192;
+
+#[derive(Serialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum KernelEventKind {
+    NvidiaXid,
+    AmdgpuReset,
+    PcieAer,
+    MachineCheck,
+    MemoryEcc,
+    OomKill,
+}
+
+/// Known faults found in the kernel log since the last reported position.
+#[derive(Serialize, Debug)]
+pub struct KernelLogReport {
+    pub source: String,
+    pub events: Vec<KernelEventSummary>,
+    /// Position after the scanned messages, stored by `save_cursor` once the
+    /// events were reported.
+    #[serde(skip)]
+    cursor: KernelLogCursor,
+}
+
+#[derive(Serialize, Debug)]
+pub struct KernelEventSummary {
+    pub kind: KernelEventKind,
+    pub count: u64,
+    /// Unix time of the last occurrence in seconds.
+    pub last_seen: Option<u64>,
+    pub last_message: String,
+    /// PCI addresses named in the messages, e.g. of the GPU with Xid errors.
+    pub devices: Vec<String>,
+    /// Xid codes for NVIDIA Xid errors.
+    pub codes: Vec<u32>,
+}
+
+/// Position in the kernel log up to which messages were already reported.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct KernelLogCursor {
+    boot_id: String,
+    kmsg_sequence: Option<u64>,
+    journal_cursor: Option<String>,
+}
+
+struct KernelMessage {
+    /// Unix time in seconds.
+    timestamp: Option<u64>,
+    text: String,
+}
+
+/// Scans the kernel log written since the last reported position for GPU and
+/// hardware faults. `/dev/kmsg` is read directly if permitted, `journalctl -k`
+/// is the fallback. The cursor is kept in the state directory and only moves
+/// with `save_cursor`, so events of a run that reports nothing are scanned
+/// again.
+pub fn collect_kernel_log(
+    settings: &ProbeSettings,
+) -> Result<KernelLogReport, Box<dyn std::error::Error>> {
+    info!("Start scanning the kernel log");
+
+    let cursor_path = config::state_dir_path()?.join(CURSOR_FILE);
+    let boot_id = fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .map(|id| id.trim().to_string())
+        .unwrap_or_default();
+
+    let mut cursor = fs::read_to_string(&cursor_path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<KernelLogCursor>(&content).ok())
+        .unwrap_or_default();
+    // kmsg sequence numbers restart with every boot
+    if cursor.boot_id != boot_id {
+        cursor.kmsg_sequence = None;
+        cursor.boot_id = boot_id;
+    }
+
+    let (source, messages) = match read_kmsg(&mut cursor) {
+        Ok(messages) => ("kmsg", messages),
+        Err(e) => {
+            info!("Cannot read /dev/kmsg ({e}), falling back to journalctl");
+            ("journald", read_journal(&mut cursor, settings)?)
+        }
+    };
+
+    let events = summarize(&messages);
+    for event in &events {
+        warn!(
+            "Kernel log reports {} {:?} events, last: {}",
+            event.count, event.kind, event.last_message
+        );
+    }
+
+    info!(
+        "Finished scanning {} kernel log messages from {source}",
+        messages.len()
+    );
+    Ok(KernelLogReport {
+        source: source.to_string(),
+        events,
+        cursor,
+    })
+}
+
+impl KernelLogReport {
+    /// Marks the scanned messages as reported.
+    pub fn save_cursor(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let cursor_path = config::state_dir_path()?.join(CURSOR_FILE);
+        fs::write(&cursor_path, serde_json::to_string(&self.cursor)?)
+            .map_err(|e| format!("failed writing {}: {e}", cursor_path.display()))?;
+        Ok(())
+    }
+}
+
+fn read_kmsg(cursor: &mut KernelLogCursor) -> std::io::Result<Vec<KernelMessage>> {
+    let mut kmsg = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open("/dev/kmsg")?;
+    let boot_time = System::boot_time();
+
+    let mut messages = Vec::new();
+    let mut record = vec![0u8; KMSG_RECORD_SIZE];
+    loop {
+        // every read returns exactly one record
+        let len = match kmsg.read(&mut record) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            // records were overwritten before they were read
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => continue,
+            Err(e) => return Err(e),
+        };
+
+        let Some((sequence, micros, text)) =
+            parse_kmsg_record(&String::from_utf8_lossy(&record[..len]))
+        else {
+            continue;
+        };
+        if cursor.kmsg_sequence.is_some_and(|last| sequence <= last) {
+            continue;
+        }
+        cursor.kmsg_sequence = Some(sequence);
+        messages.push(KernelMessage {
+            timestamp: Some(boot_time + micros / 1_000_000),
+            text,
+        });
+    }
+
+    Ok(messages)
+}
+
+/// Parses a `/dev/kmsg` record `prio,seq,usec,flags;message` followed by
+/// optional continuation lines, into the sequence number, the microseconds
+/// since boot and the message.
+fn parse_kmsg_record(record: &str) -> Option<(u64, u64, String)> {
+    let (header, message) = record.split_once(';')?;
+    let mut fields = header.split(',');
+    let _priority = fields.next()?;
+    let sequence = fields.next()?.parse::<u64>().ok()?;
+    let micros = fields.next()?.parse::<u64>().ok()?;
+    let text = message.lines().next().unwrap_or_default().to_string();
+    Some((sequence, micros, text))
+}
+
+fn read_journal(
+    cursor: &mut KernelLogCursor,
+    settings: &ProbeSettings,
+) -> Result<Vec<KernelMessage>, Box<dyn std::error::Error>> {
+    let after_cursor = cursor
+        .journal_cursor
+        .as_ref()
+        .map(|journal_cursor| format!("--after-cursor={journal_cursor}"));
+    let mut args = vec!["-k", "-o", "json", "--no-pager"];
+    if let Some(after_cursor) = &after_cursor {
+        args.push(after_cursor);
+    }
+
+    let output = probe::run("journalctl", &args, settings);
+    if output.status != ProbeStatus::Ok {
+        return Err(format!("journalctl failed with {:?}", output.status).into());
+    }
+
+    let mut messages = Vec::new();
+    for line in output.stdout.lines() {
+        // the last line is cut off if the output limit is hit
+        let Some((journal_cursor, message)) = parse_journal_entry(line) else {
+            continue;
+        };
+        cursor.journal_cursor = Some(journal_cursor);
+        messages.extend(message);
+    }
+    Ok(messages)
+}
+
+/// Parses one entry of `journalctl -o json` into its cursor and message.
+/// Messages that are no valid UTF-8 are exported as byte arrays and skipped.
+fn parse_journal_entry(line: &str) -> Option<(String, Option<KernelMessage>)> {
+    let entry = serde_json::from_str::<serde_json::Value>(line).ok()?;
+    let journal_cursor = entry.get("__CURSOR")?.as_str()?.to_string();
+    let message = entry
+        .get("MESSAGE")
+        .and_then(|message| message.as_str())
+        .map(|text| KernelMessage {
+            timestamp: entry
+                .get("__REALTIME_TIMESTAMP")
+                .and_then(|timestamp| timestamp.as_str())
+                .and_then(|timestamp| timestamp.parse::<u64>().ok())
+                .map(|micros| micros / 1_000_000),
+            text: text.to_string(),
+        });
+    Some((journal_cursor, message))
+}
+
+fn classify(message: &str) -> Option<KernelEventKind> {
+    let kind = if message.contains("NVRM: Xid") {
+        KernelEventKind::NvidiaXid
+    } else if message.contains("amdgpu")
+        && (message.contains("GPU reset")
+            || message.contains("ring") && message.contains("timeout"))
+    {
+        KernelEventKind::AmdgpuReset
+    } else if message.contains("AER:") {
+        KernelEventKind::PcieAer
+    } else if message.starts_with("mce:")
+        || message.contains("Machine check events logged")
+        || message.contains("[Hardware Error]")
+    {
+        KernelEventKind::MachineCheck
+    } else if message.starts_with("EDAC") && (message.contains(" CE ") || message.contains(" UE "))
+    {
+        KernelEventKind::MemoryEcc
+    } else if message.starts_with("Out of memory:") || message.contains("oom-kill:") {
+        KernelEventKind::OomKill
+    } else {
+        return None;
+    };
+    Some(kind)
+}
+
+/// Extracts the Xid code from `NVRM: Xid (PCI:0000:17:00): 79, pid=...`.
+fn parse_xid_code(message: &str) -> Option<u32> {
+    let (_, rest) = message.split_once("):")?;
+    rest.split(',').next()?.trim().parse::<u32>().ok()
+}
+
+/// Finds a PCI address such as `0000:17:00.0` or the `PCI:0000:17:00` form of
+/// the NVIDIA driver.
+fn find_pci_address(message: &str) -> Option<String> {
+    message
+        .split(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | ','))
+        .map(|token| token.trim_start_matches("PCI:").trim_end_matches(':'))
+        .find(|token| {
+            let parts: Vec<&str> = token.split([':', '.']).collect();
+            (parts.len() == 3 || parts.len() == 4)
+                && parts[0].len() == 4
+                && parts
+                    .iter()
+                    .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_hexdigit()))
+        })
+        .map(str::to_lowercase)
+}
+
+fn summarize(messages: &[KernelMessage]) -> Vec<KernelEventSummary> {
+    let mut summaries: BTreeMap<KernelEventKind, KernelEventSummary> = BTreeMap::new();
+
+    for message in messages {
+        let Some(kind) = classify(&message.text) else {
+            continue;
+        };
+        let summary = summaries.entry(kind).or_insert_with(|| KernelEventSummary {
+            kind,
+            count: 0,
+            last_seen: None,
+            last_message: String::new(),
+            devices: Vec::new(),
+            codes: Vec::new(),
+        });
+
+        summary.count += 1;
+        summary.last_seen = message.timestamp.or(summary.last_seen);
+        summary.last_message = message.text.to_owned();
+        if let Some(device) = find_pci_address(&message.text)
+            && !summary.devices.contains(&device)
+        {
+            summary.devices.push(device);
+        }
+        if kind == KernelEventKind::NvidiaXid
+            && let Some(code) = parse_xid_code(&message.text)
+            && !summary.codes.contains(&code)
+        {
+            summary.codes.push(code);
+        }
+    }
+
+    summaries.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: &str) -> KernelMessage {
+        KernelMessage {
+            timestamp: Some(1_700_000_000),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_classify_sample_lines() {
+        let samples = [
+            (
+                "NVRM: Xid (PCI:0000:17:00): 79, pid=1234, name=python, GPU has fallen off the bus.",
+                Some(KernelEventKind::NvidiaXid),
+            ),
+            (
+                "amdgpu 0000:03:00.0: amdgpu: GPU reset begin!",
+                Some(KernelEventKind::AmdgpuReset),
+            ),
+            (
+                "[drm:amdgpu_job_timedout [amdgpu]] *ERROR* ring gfx_0.0.0 timeout, signaled seq=1, emitted seq=2",
+                Some(KernelEventKind::AmdgpuReset),
+            ),
+            (
+                "pcieport 0000:00:01.1: AER: Corrected error received: 0000:01:00.0",
+                Some(KernelEventKind::PcieAer),
+            ),
+            (
+                "mce: [Hardware Error]: Machine check events logged",
+                Some(KernelEventKind::MachineCheck),
+            ),
+            (
+                "EDAC MC0: 1 CE memory read error on CPU_SrcID#0_MC#0_Chan#1_DIMM#0",
+                Some(KernelEventKind::MemoryEcc),
+            ),
+            (
+                "Out of memory: Killed process 4242 (python) total-vm:1234kB, anon-rss:1000kB",
+                Some(KernelEventKind::OomKill),
+            ),
+            (
+                "oom-kill:constraint=CONSTRAINT_MEMCG,nodemask=(null),task=python,pid=4242",
+                Some(KernelEventKind::OomKill),
+            ),
+            ("EXT4-fs (nvme0n1p2): mounted filesystem", None),
+            (
+                "amdgpu 0000:03:00.0: amdgpu: SMU is initialized successfully!",
+                None,
+            ),
+        ];
+
+        for (line, expected) in samples {
+            assert_eq!(classify(line), expected, "{line}");
+        }
+    }
+
+    #[test]
+    fn test_parse_kmsg_record() {
+        let record = "3,1042,81234567,-;NVRM: Xid (PCI:0000:17:00): 13, Graphics Exception\n SUBSYSTEM=pci\n DEVICE=+pci:0000:17:00.0\n";
+
+        let (sequence, micros, text) = parse_kmsg_record(record).unwrap();
+
+        assert_eq!(sequence, 1042);
+        assert_eq!(micros, 81_234_567);
+        assert_eq!(text, "NVRM: Xid (PCI:0000:17:00): 13, Graphics Exception");
+        assert!(parse_kmsg_record("garbage").is_none());
+    }
+
+    #[test]
+    fn test_parse_journal_entry() {
+        let line = r#"{"__CURSOR":"s=6a1b;i=4f2;b=9c3e;m=2f4;t=60a1;x=1e2","__REALTIME_TIMESTAMP":"1700000123456789","MESSAGE":"AER: Uncorrected (Fatal) error received: 0000:41:00.0","_TRANSPORT":"kernel"}"#;
+
+        let (journal_cursor, message) = parse_journal_entry(line).unwrap();
+        let message = message.unwrap();
+
+        assert_eq!(journal_cursor, "s=6a1b;i=4f2;b=9c3e;m=2f4;t=60a1;x=1e2");
+        assert_eq!(message.timestamp, Some(1_700_000_123));
+        assert!(message.text.starts_with("AER: Uncorrected"));
+        assert!(parse_journal_entry(r#"{"__CURSOR":"s=6a1b"#).is_none());
+    }
+
+    #[test]
+    fn test_summarize_counts_and_devices() {
+        let messages = [
+            message(
+                "NVRM: Xid (PCI:0000:17:00): 79, pid=1234, name=python, GPU has fallen off the bus.",
+            ),
+            message(
+                "NVRM: Xid (PCI:0000:2A:00): 48, pid=1234, name=python, An uncorrectable double bit error",
+            ),
+            message(
+                "NVRM: Xid (PCI:0000:17:00): 79, pid=1300, name=python, GPU has fallen off the bus.",
+            ),
+            message("pcieport 0000:00:01.1: AER: Corrected error received: 0000:01:00.0"),
+            message("systemd[1]: Started Session 4 of User ubuntu."),
+        ];
+
+        let events = summarize(&messages);
+
+        assert_eq!(events.len(), 2);
+        let xid = &events[0];
+        assert_eq!(xid.kind, KernelEventKind::NvidiaXid);
+        assert_eq!(xid.count, 3);
+        assert_eq!(xid.codes, vec![79, 48]);
+        assert_eq!(xid.devices, vec!["0000:17:00", "0000:2a:00"]);
+        assert_eq!(xid.last_seen, Some(1_700_000_000));
+        assert_eq!(events[1].kind, KernelEventKind::PcieAer);
+        assert_eq!(events[1].devices, vec!["0000:00:01.1"]);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::config;
+use log::info;
+use std::collections::BTreeMap;
+
+const MAX_NAME_LENGTH: usize = 63;
+const MAX_PREFIX_LENGTH: usize = 253;
+
+/// Merges the labels from the settings with the `--label key=value` arguments.
+/// Arguments win over the settings and an empty value (`key=`) removes a label.
+pub(crate) fn merge_labels(
+    configured: &BTreeMap<String, String>,
+    arguments: &[String],
+) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+    let mut labels = BTreeMap::new();
+    for (key, value) in configured {
+        validate_label(key, value)
+            .map_err(|e| format!("invalid label {key} in the settings file: {e}"))?;
+        labels.insert(key.to_owned(), value.to_owned());
+    }
+
+    for argument in arguments {
+        let (key, value) = argument
+            .split_once('=')
+            .ok_or_else(|| format!("invalid label {argument}: expected key=value"))?;
+        if value.is_empty() {
+            info!("Removing label {key}");
+            labels.remove(key);
+            continue;
+        }
+        validate_label(key, value).map_err(|e| format!("invalid label {argument}: {e}"))?;
+        labels.insert(key.to_string(), value.to_string());
+    }
+
+    Ok(labels)
+}
+
+/// Stores the labels in the settings file, so the next runs of the timer
+/// send them without `--label` arguments.
+pub(crate) fn persist_labels(
+    labels: &BTreeMap<String, String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    config::update_settings_table("labels", toml::Value::try_from(labels)?)
+}
+
+/// Validates a label like Kubernetes does, so labels can be used for node
+/// selection as they are: the key is an optional DNS subdomain prefix and a
+/// name, e.g. `exalsius.ai/tier`, the value is a name.
+fn validate_label(key: &str, value: &str) -> Result<(), String> {
+    let name = match key.split_once('/') {
+        Some((prefix, name)) => {
+            validate_prefix(prefix)?;
+            name
+        }
+        None => key,
+    };
+
+    if name.is_empty() {
+        return Err(String::from("the key must not be empty"));
+    }
+    validate_name(name).map_err(|e| format!("key {e}"))?;
+    validate_name(value).map_err(|e| format!("value {e}"))
+}
+
+fn validate_name(name: &str) -> Result<(), String> {
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(format!("must be at most {MAX_NAME_LENGTH} characters"));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(String::from(
+            "may only contain letters, digits, '-', '_' and '.'",
+        ));
+    }
+    if !name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        || !name.ends_with(|c: char| c.is_ascii_alphanumeric())
+    {
+        return Err(String::from("must start and end with a letter or digit"));
+    }
+    Ok(())
+}
+
+fn validate_prefix(prefix: &str) -> Result<(), String> {
+    let valid = !prefix.is_empty()
+        && prefix.len() <= MAX_PREFIX_LENGTH
+        && prefix.split('.').all(|part| {
+            !part.is_empty()
+                && part.len() <= MAX_NAME_LENGTH
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+                && !part.starts_with('-')
+                && !part.ends_with('-')
+        });
+
+    if !valid {
+        return Err(format!("key prefix {prefix} is no DNS subdomain"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_label() {
+        assert!(validate_label("region", "eu-central").is_ok());
+        assert!(validate_label("exalsius.ai/tier", "gold").is_ok());
+        assert!(validate_label("maintenance", "true").is_ok());
+
+        assert!(validate_label("", "gold").is_err());
+        assert!(validate_label("exalsius.ai/", "gold").is_err());
+        assert!(validate_label("Exalsius.AI/tier", "gold").is_err());
+        assert!(validate_label("-tier", "gold").is_err());
+        assert!(validate_label("tier", "gold plus").is_err());
+        assert!(validate_label("tier", &"a".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn test_merge_labels() {
+        let configured = BTreeMap::from([
+            (String::from("region"), String::from("eu-central")),
+            (String::from("maintenance"), String::from("true")),
+        ]);
+        let arguments = vec![String::from("tier=gold"), String::from("maintenance=")];
+
+        let labels = merge_labels(&configured, &arguments).expect("labels should be valid");
+
+        assert_eq!(
+            labels,
+            BTreeMap::from([
+                (String::from("region"), String::from("eu-central")),
+                (String::from("tier"), String::from("gold")),
+            ])
+        );
+        assert!(merge_labels(&configured, &[String::from("tier")]).is_err());
+        assert!(merge_labels(&configured, &[String::from("tier=a b")]).is_err());
+    }
+}
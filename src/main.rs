@@ -1,14 +1,34 @@
+mod changes;
+mod collector;
 mod config;
+mod directives;
+mod dmi;
+mod facts;
+mod gpu_metrics;
 mod hardware;
+mod health;
 mod heartbeat;
+mod http;
+mod interconnect;
+mod kernel_log;
+mod labels;
+mod metrics;
+mod mtls;
+mod network;
+mod probe;
 mod self_register;
+mod signing;
 mod software;
+mod spool;
+mod storage;
+mod sysfs;
 mod system;
+mod topology;
 
 use crate::self_register::SelfRegisterParams;
 use argh::FromArgs;
 use env_logger::{Builder, Env};
-use log::{error, info};
+use log::{error, info, warn};
 use std::process::ExitCode;
 
 #[derive(FromArgs)]
@@ -65,10 +85,64 @@ struct CliArguments {
     /// skip systemd service creation when running self-registering
     #[argh(switch)]
     skip_systemd: bool,
+
+    /// a node label as key=value, can be repeated. An empty value removes the label.
+    #[argh(option)]
+    label: Vec<String>,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Health(HealthCommand),
+    Spool(SpoolCommand),
+}
+
+#[derive(FromArgs)]
+/// Evaluate the node health checks, print the report and exit non-zero if a check fails.
+#[argh(subcommand, name = "health")]
+struct HealthCommand {}
+
+#[derive(FromArgs)]
+/// Inspect, replay or empty the heartbeats spooled while the API was unreachable.
+#[argh(subcommand, name = "spool")]
+struct SpoolCommand {
+    #[argh(subcommand)]
+    action: SpoolAction,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum SpoolAction {
+    Status(SpoolStatusCommand),
+    Flush(SpoolFlushCommand),
+    Clear(SpoolClearCommand),
 }
 
+#[derive(FromArgs)]
+/// Print the number, size and age of the spooled heartbeats.
+#[argh(subcommand, name = "status")]
+struct SpoolStatusCommand {}
+
+#[derive(FromArgs)]
+/// Send the spooled heartbeats now.
+#[argh(subcommand, name = "flush")]
+struct SpoolFlushCommand {}
+
+#[derive(FromArgs)]
+/// Delete the spooled heartbeats without sending them.
+#[argh(subcommand, name = "clear")]
+struct SpoolClearCommand {}
+
 fn main() -> ExitCode {
-    Builder::from_env(Env::default().default_filter_or("info")).init();
+    let settings = config::load_settings();
+    let log_level = settings
+        .as_ref()
+        .map_or("info", |settings| settings.logging.level.as_str());
+    Builder::from_env(Env::default().default_filter_or(log_level)).init();
 
     let cli_arguments: CliArguments = argh::from_env();
 
@@ -79,23 +153,67 @@ fn main() -> ExitCode {
 
     info!("Starting client hardware info tool");
 
-    let node_hardware = match hardware::collect_client_hardware() {
-        Ok(node_hardware) => node_hardware,
+    let settings = match settings {
+        Ok(settings) => settings,
         Err(e) => {
             error!("Error: {}", e);
             return ExitCode::FAILURE;
         }
     };
 
-    let node_software = software::collect_software_info();
+    match cli_arguments.command {
+        Some(Command::Health(_)) => return run_health(settings),
+        Some(Command::Spool(command)) => {
+            return run_spool(
+                command.action,
+                &settings,
+                cli_arguments.node_id,
+                cli_arguments.api_url,
+                cli_arguments.access_token,
+            );
+        }
+        None => {}
+    }
 
-    let node_system = system::collect_system_info();
+    let labels = match labels::merge_labels(&settings.labels, &cli_arguments.label) {
+        Ok(labels) => labels,
+        Err(e) => {
+            error!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // labels given as arguments are kept for the following runs of the timer
+    if labels != settings.labels
+        && let Err(e) = labels::persist_labels(&labels)
+    {
+        error!("Error: {}", e);
+        return ExitCode::FAILURE;
+    }
+
+    let mut inventory = collector::collect_inventory(&settings);
 
     if cli_arguments.skip_heartbeat {
         info!("Hardware, Software, and OS details collected (heartbeat skipped by flag)");
         return ExitCode::SUCCESS;
     }
 
+    inventory.changes = match changes::detect_changes(&inventory) {
+        Ok(changes) => Some(changes),
+        Err(e) => {
+            warn!("Failed detecting inventory changes: {}", e);
+            None
+        }
+    };
+
+    let (client, identity) = match api_client(&settings) {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
     if cli_arguments.self_register {
         if cli_arguments.register_token.is_none()
             || cli_arguments.username.is_none()
@@ -122,24 +240,52 @@ fn main() -> ExitCode {
         let port = cli_arguments.port.unwrap();
         let skip_systemd = cli_arguments.skip_systemd;
 
+        let certificate_request = if settings.mtls.enabled {
+            match mtls::CertificateRequest::generate(&hostname) {
+                Ok(request) => Some(request),
+                Err(e) => {
+                    error!("Error: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        } else {
+            None
+        };
+
+        let signing_key = match signing::SigningKey::generate() {
+            Ok(key) => key,
+            Err(e) => {
+                error!("Error: {}", e);
+                return ExitCode::FAILURE;
+            }
+        };
+
         let self_register_params = SelfRegisterParams {
+            client: &client,
             api_url: &api_url,
             register_token: &register_token,
             username: &username,
-            node_hardware: &node_hardware,
-            node_software: &node_software,
-            node_system: &node_system,
+            inventory: &inventory,
+            labels: &labels,
             ssh_key_id: &private_key_id,
             hostname: &hostname,
             ip_addr: &ip_addr,
             port,
             skip_systemd,
             price_per_hour: 0.0,
+            heartbeat_interval_minutes: settings.heartbeat.interval_minutes,
+            certificate_request: certificate_request.as_ref(),
+            signing_key: Some(&signing_key),
         };
 
         return match self_register::self_register(self_register_params) {
             Ok(_) => {
                 info!("Successfully registered node");
+                if let Some(hardware) = &inventory.hardware
+                    && let Err(e) = health::store_registered_gpu_count(hardware.gpu_count)
+                {
+                    warn!("Failed storing the registered GPU count: {}", e);
+                }
                 ExitCode::SUCCESS
             }
             Err(e) => {
@@ -161,26 +307,198 @@ fn main() -> ExitCode {
         }
     };
 
-    let new_auth_tkn = match heartbeat::send_heartbeat(
-        &node_id,
-        &api_endpoint,
-        &auth_tkn,
-        &node_hardware,
-        &node_software,
-        &node_system,
-    ) {
-        Ok(new_auth_tkn) => new_auth_tkn,
+    if settings.mtls.enabled && identity.is_none() {
+        warn!("mTLS is enabled but no client certificate is stored, connecting without it");
+    }
+
+    let signing_key = match signing::SigningKey::load() {
+        Ok(signing_key) => signing_key,
         Err(e) => {
             error!("Error: {}", e);
             return ExitCode::FAILURE;
         }
     };
+    let mut new_auth_tkn = auth_tkn.clone();
+    let api = heartbeat::NodeApi {
+        client: &client,
+        api_url: &api_endpoint,
+        node_id: &node_id,
+        signing_key: signing_key.as_ref(),
+    };
+    let directives = heartbeat::send_heartbeat(
+        &api,
+        &mut new_auth_tkn,
+        &inventory,
+        &labels,
+        &settings.spool,
+    );
 
-    if let Err(e) = config::write_new_auth_token(&new_auth_tkn) {
+    let reported = match &directives {
+        Ok(_) => true,
+        Err(e) => heartbeat::is_spooled(e.as_ref()),
+    };
+    if reported {
+        commit_reported_state(&inventory);
+    }
+
+    // a token handed out before a failed request replaces the previous one
+    if new_auth_tkn != auth_tkn
+        && let Err(e) = config::write_new_auth_token(&new_auth_tkn)
+    {
         error!("Error: {}", e);
         return ExitCode::FAILURE;
     }
 
+    match directives {
+        Ok(directives) => directives::apply(&directives, &settings),
+        Err(e) => {
+            error!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Some(identity) = &identity
+        && let Err(e) = mtls::renew_if_expiring(&api, &settings.mtls, identity)
+    {
+        warn!("Failed renewing the client certificate: {}", e);
+    }
+
     info!("Finished client hardware info tool");
     ExitCode::SUCCESS
 }
+
+/// Moves the kernel log cursor and the change baseline past the events of a
+/// heartbeat that was delivered or spooled. Any other run reports them again.
+fn commit_reported_state(inventory: &collector::Inventory) {
+    if inventory.changes.is_some()
+        && let Err(e) = changes::commit_snapshot(inventory)
+    {
+        warn!("Failed storing the inventory snapshot: {}", e);
+    }
+    if let Some(kernel_log) = &inventory.kernel_log
+        && let Err(e) = kernel_log.save_cursor()
+    {
+        warn!("Failed saving the kernel log cursor: {}", e);
+    }
+}
+
+/// Client for the API, presenting the stored client certificate with mTLS.
+fn api_client(
+    settings: &config::Settings,
+) -> Result<(reqwest::blocking::Client, Option<mtls::ClientIdentity>), Box<dyn std::error::Error>> {
+    let identity = if settings.mtls.enabled {
+        mtls::load_identity()?
+    } else {
+        None
+    };
+    let client = http::build_client(&settings.http, identity.as_ref())?;
+    Ok((client, identity))
+}
+
+fn run_health(mut settings: config::Settings) -> ExitCode {
+    // only the inventory the checks need
+    for name in ["facts", "gpu_metrics", "metrics", "kernel_log"] {
+        settings.collectors.disabled.push(name.to_string());
+    }
+
+    let inventory = collector::collect_inventory(&settings);
+    let Some(report) = inventory.health else {
+        return ExitCode::FAILURE;
+    };
+
+    report.print();
+    if report.status == health::HealthStatus::Fail {
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+fn run_spool(
+    action: SpoolAction,
+    settings: &config::Settings,
+    node_id: Option<String>,
+    api_url: Option<String>,
+    access_token: Option<String>,
+) -> ExitCode {
+    let spool = match spool::Spool::open(&settings.spool) {
+        Ok(spool) => spool,
+        Err(e) => {
+            error!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match action {
+        SpoolAction::Status(_) => match spool.status() {
+            Ok(status) => {
+                println!(
+                    "{} spooled heartbeats ({} with events, {} bytes)",
+                    status.entries, status.events, status.bytes
+                );
+                if let Some(age) = status.oldest_age {
+                    println!("Oldest spooled {} minutes ago", age.as_secs() / 60);
+                }
+            }
+            Err(e) => {
+                error!("Error: {}", e);
+                return ExitCode::FAILURE;
+            }
+        },
+        SpoolAction::Flush(_) => {
+            let (node_id, api_endpoint, auth_tkn) =
+                match config::lookup_configuration(node_id, api_url, access_token) {
+                    Ok(configuration) => configuration,
+                    Err(e) => {
+                        error!("Error: {}", e);
+                        return ExitCode::FAILURE;
+                    }
+                };
+
+            let (client, _) = match api_client(settings) {
+                Ok(client) => client,
+                Err(e) => {
+                    error!("Error: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+
+            let signing_key = match signing::SigningKey::load() {
+                Ok(signing_key) => signing_key,
+                Err(e) => {
+                    error!("Error: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let mut new_auth_tkn = auth_tkn.clone();
+            let api = heartbeat::NodeApi {
+                client: &client,
+                api_url: &api_endpoint,
+                node_id: &node_id,
+                signing_key: signing_key.as_ref(),
+            };
+            let result = heartbeat::flush_spool(&api, &mut new_auth_tkn, &settings.spool);
+            if new_auth_tkn != auth_tkn
+                && let Err(e) = config::write_new_auth_token(&new_auth_tkn)
+            {
+                error!("Error: {}", e);
+                return ExitCode::FAILURE;
+            }
+
+            match result {
+                Ok(replayed) => println!("Sent {replayed} spooled heartbeats"),
+                Err(e) => {
+                    error!("Error: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        SpoolAction::Clear(_) => match spool.clear() {
+            Ok(cleared) => println!("Deleted {cleared} spooled heartbeats"),
+            Err(e) => {
+                error!("Error: {}", e);
+                return ExitCode::FAILURE;
+            }
+        },
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,261 @@
+use log::info;
+use serde::Serialize;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use sysinfo::{Disks, LoadAvg, Networks, System};
+
+/// Window over which CPU usage and network throughput are sampled. It must be
+/// at least `sysinfo::MINIMUM_CPU_UPDATE_INTERVAL`.
+const SAMPLE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Current utilization of the host, next to the static capacity in
+/// `NodeHardware`.
+#[derive(Serialize, Debug)]
+pub struct HostMetrics {
+    pub sample_window_ms: u64,
+    pub cpu_usage_percent: f32,
+    pub load_average: LoadAverage,
+    pub memory_total_bytes: u64,
+    pub memory_used_bytes: u64,
+    pub memory_available_bytes: u64,
+    pub swap_total_bytes: u64,
+    pub swap_used_bytes: u64,
+    pub disks: Vec<DiskUsage>,
+    pub network: Vec<InterfaceThroughput>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LoadAverage {
+    pub one: f64,
+    pub five: f64,
+    pub fifteen: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DiskUsage {
+    pub mount_point: String,
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub used_percent: f32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InterfaceThroughput {
+    pub name: String,
+    pub rx_bytes_per_sec: u64,
+    pub tx_bytes_per_sec: u64,
+    pub rx_bytes_total: u64,
+    pub tx_bytes_total: u64,
+}
+
+/// Raw readings of one sampling cycle, mapped into `HostMetrics` by
+/// `from_sample`.
+struct Sample {
+    window: Duration,
+    cpu_usage_percent: f32,
+    load: LoadAvg,
+    memory_total_bytes: u64,
+    memory_used_bytes: u64,
+    memory_available_bytes: u64,
+    swap_total_bytes: u64,
+    swap_used_bytes: u64,
+    disks: Vec<DiskSample>,
+    network: Vec<InterfaceThroughput>,
+}
+
+struct DiskSample {
+    mount_point: String,
+    total_bytes: u64,
+    available_bytes: u64,
+}
+
+/// Samples the host utilization. `sys` is the instance the hardware collector
+/// refreshed, it stays locked for the sampling window.
+pub fn collect_host_metrics(sys: &Mutex<System>) -> HostMetrics {
+    info!("Start collecting host metrics");
+
+    let mut sys = sys.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut networks = Networks::new_with_refreshed_list();
+    sys.refresh_cpu_usage();
+
+    // CPU usage and throughput are only known as the difference of two samples
+    let started = Instant::now();
+    thread::sleep(SAMPLE_WINDOW);
+    sys.refresh_cpu_usage();
+    networks.refresh(true);
+    let window = started.elapsed();
+
+    sys.refresh_memory();
+
+    let network = networks
+        .iter()
+        .filter(|(name, _)| name.as_str() != "lo")
+        .map(|(name, data)| InterfaceThroughput {
+            name: name.to_owned(),
+            rx_bytes_per_sec: per_second(data.received(), window),
+            tx_bytes_per_sec: per_second(data.transmitted(), window),
+            rx_bytes_total: data.total_received(),
+            tx_bytes_total: data.total_transmitted(),
+        })
+        .collect();
+
+    let disks = Disks::new_with_refreshed_list()
+        .iter()
+        .map(|disk| DiskSample {
+            mount_point: disk.mount_point().display().to_string(),
+            total_bytes: disk.total_space(),
+            available_bytes: disk.available_space(),
+        })
+        .collect();
+
+    let metrics = from_sample(Sample {
+        window,
+        cpu_usage_percent: sys.global_cpu_usage(),
+        load: System::load_average(),
+        memory_total_bytes: sys.total_memory(),
+        memory_used_bytes: sys.used_memory(),
+        memory_available_bytes: sys.available_memory(),
+        swap_total_bytes: sys.total_swap(),
+        swap_used_bytes: sys.used_swap(),
+        disks,
+        network,
+    });
+
+    info!(
+        "CPU usage {:.1} %, load average {:.2} {:.2} {:.2}, memory used {} of {} bytes",
+        metrics.cpu_usage_percent,
+        metrics.load_average.one,
+        metrics.load_average.five,
+        metrics.load_average.fifteen,
+        metrics.memory_used_bytes,
+        metrics.memory_total_bytes
+    );
+    info!("Finished collecting host metrics");
+    metrics
+}
+
+fn from_sample(sample: Sample) -> HostMetrics {
+    let mut disks: Vec<DiskUsage> = sample
+        .disks
+        .into_iter()
+        .filter(|disk| disk.total_bytes > 0)
+        .map(|disk| {
+            let used_bytes = disk.total_bytes.saturating_sub(disk.available_bytes);
+            DiskUsage {
+                mount_point: disk.mount_point,
+                total_bytes: disk.total_bytes,
+                used_bytes,
+                used_percent: percent(used_bytes, disk.total_bytes),
+            }
+        })
+        .collect();
+    disks.sort_by(|a, b| a.mount_point.cmp(&b.mount_point));
+    disks.dedup_by(|a, b| a.mount_point == b.mount_point);
+
+    let mut network = sample.network;
+    network.sort_by(|a, b| a.name.cmp(&b.name));
+
+    HostMetrics {
+        sample_window_ms: sample.window.as_millis() as u64,
+        cpu_usage_percent: sample.cpu_usage_percent,
+        load_average: LoadAverage {
+            one: sample.load.one,
+            five: sample.load.five,
+            fifteen: sample.load.fifteen,
+        },
+        memory_total_bytes: sample.memory_total_bytes,
+        memory_used_bytes: sample.memory_used_bytes,
+        memory_available_bytes: sample.memory_available_bytes,
+        swap_total_bytes: sample.swap_total_bytes,
+        swap_used_bytes: sample.swap_used_bytes,
+        disks,
+        network,
+    }
+}
+
+fn per_second(bytes: u64, window: Duration) -> u64 {
+    if window.is_zero() {
+        return 0;
+    }
+    (bytes as f64 / window.as_secs_f64()).round() as u64
+}
+
+fn percent(part: u64, total: u64) -> f32 {
+    if total == 0 {
+        return 0.0;
+    }
+    (part as f64 * 100.0 / total as f64) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rates() {
+        assert_eq!(per_second(3000, Duration::from_millis(1500)), 2000);
+        assert_eq!(per_second(3000, Duration::ZERO), 0);
+        assert_eq!(percent(25, 200), 12.5);
+        assert_eq!(percent(1, 0), 0.0);
+    }
+
+    #[test]
+    fn test_from_sample() {
+        let disk = |mount_point: &str, total_bytes, available_bytes| DiskSample {
+            mount_point: mount_point.to_string(),
+            total_bytes,
+            available_bytes,
+        };
+        let metrics = from_sample(Sample {
+            window: Duration::from_millis(1002),
+            cpu_usage_percent: 37.5,
+            load: LoadAvg {
+                one: 4.5,
+                five: 3.25,
+                fifteen: 2.0,
+            },
+            memory_total_bytes: 512 << 30,
+            memory_used_bytes: 128 << 30,
+            memory_available_bytes: 384 << 30,
+            swap_total_bytes: 8 << 30,
+            swap_used_bytes: 1 << 30,
+            disks: vec![
+                disk("/var/lib/jobs", 4000, 1000),
+                disk("/", 1000, 750),
+                // pseudo filesystems and bind mounts of the same mount point
+                disk("/proc", 0, 0),
+                disk("/", 1000, 750),
+            ],
+            network: Vec::new(),
+        });
+
+        assert_eq!(metrics.sample_window_ms, 1002);
+        assert_eq!(metrics.cpu_usage_percent, 37.5);
+        assert_eq!(metrics.load_average.one, 4.5);
+        assert_eq!(metrics.load_average.five, 3.25);
+        assert_eq!(metrics.load_average.fifteen, 2.0);
+        assert_eq!(metrics.memory_total_bytes, 512 << 30);
+        assert_eq!(metrics.memory_used_bytes, 128 << 30);
+        assert_eq!(metrics.memory_available_bytes, 384 << 30);
+        assert_eq!(metrics.swap_total_bytes, 8 << 30);
+        assert_eq!(metrics.swap_used_bytes, 1 << 30);
+
+        let disks: Vec<_> = metrics
+            .disks
+            .iter()
+            .map(|disk| {
+                (
+                    disk.mount_point.as_str(),
+                    disk.total_bytes,
+                    disk.used_bytes,
+                    disk.used_percent,
+                )
+            })
+            .collect();
+        assert_eq!(
+            disks,
+            [("/", 1000, 250, 25.0), ("/var/lib/jobs", 4000, 3000, 75.0)]
+        );
+    }
+}
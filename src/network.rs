@@ -0,0 +1,297 @@
+use crate::sysfs::read_trimmed;
+use crate::topology;
+use log::{info, warn};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// `ARPHRD_INFINIBAND` from `if_arp.h`, reported in `/sys/class/net/*/type`.
+const ARPHRD_INFINIBAND: u32 = 32;
+const ARPHRD_ETHER: u32 = 1;
+
+#[derive(Serialize, Debug, Default)]
+pub struct NodeNetwork {
+    pub interfaces: Vec<NetworkInterface>,
+    pub rdma_devices: Vec<RdmaDevice>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub kind: InterfaceKind,
+    pub mac_address: Option<String>,
+    pub mtu: Option<u32>,
+    pub operstate: Option<String>,
+    pub speed_mbps: Option<u32>,
+    pub driver: Option<String>,
+    pub pci_address: Option<String>,
+    pub bond_members: Vec<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InterfaceKind {
+    Ethernet,
+    Infiniband,
+    Bond,
+    Other,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RdmaDevice {
+    pub name: String,
+    pub node_guid: Option<String>,
+    pub firmware_version: Option<String>,
+    pub pci_address: Option<String>,
+    pub netdevs: Vec<String>,
+    pub ports: Vec<RdmaPort>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RdmaPort {
+    pub port: u32,
+    pub state: Option<String>,
+    pub physical_state: Option<String>,
+    pub rate: Option<String>,
+    pub rate_gbps: Option<f32>,
+    pub link_layer: Option<String>,
+}
+
+pub fn collect_network() -> NodeNetwork {
+    collect_network_from(Path::new("/sys/class"))
+}
+
+fn collect_network_from(class_root: &Path) -> NodeNetwork {
+    info!("Start collecting network information");
+
+    let interfaces = list_interfaces(&class_root.join("net")).unwrap_or_else(|e| {
+        warn!("Failed reading network interfaces: {e}");
+        Vec::new()
+    });
+    for interface in &interfaces {
+        info!(
+            "Network interface {} ({:?}) is {} with speed of {} Mbps",
+            interface.name,
+            interface.kind,
+            interface.operstate.as_deref().unwrap_or("unknown"),
+            interface.speed_mbps.unwrap_or(0)
+        );
+    }
+
+    // most machines have no RDMA capable devices, so a missing class is fine
+    let rdma_devices = list_rdma_devices(&class_root.join("infiniband")).unwrap_or_default();
+    for device in &rdma_devices {
+        for port in &device.ports {
+            info!(
+                "RDMA device {} port {} is {} at {}",
+                device.name,
+                port.port,
+                port.state.as_deref().unwrap_or("unknown"),
+                port.rate.as_deref().unwrap_or("unknown rate")
+            );
+        }
+    }
+
+    info!("Finished collecting network information");
+    NodeNetwork {
+        interfaces,
+        rdma_devices,
+    }
+}
+
+fn link_name(path: &Path) -> Option<String> {
+    fs::read_link(path)
+        .ok()?
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+}
+
+fn list_interfaces(net_root: &Path) -> std::io::Result<Vec<NetworkInterface>> {
+    let mut interfaces = Vec::new();
+
+    for entry in fs::read_dir(net_root)? {
+        let entry = entry?;
+        let path = entry.path();
+        let is_bond = path.join("bonding").is_dir();
+        // only physical NICs and bonds; lo, bridges, veth and tunnels have no device
+        if !is_bond && !path.join("device").exists() {
+            continue;
+        }
+
+        let kind = if is_bond {
+            InterfaceKind::Bond
+        } else {
+            match read_trimmed(&path.join("type")).and_then(|t| t.parse::<u32>().ok()) {
+                Some(ARPHRD_ETHER) => InterfaceKind::Ethernet,
+                Some(ARPHRD_INFINIBAND) => InterfaceKind::Infiniband,
+                _ => InterfaceKind::Other,
+            }
+        };
+
+        let bond_members = read_trimmed(&path.join("bonding/slaves"))
+            .map(|slaves| slaves.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default();
+
+        // reading the speed fails (or yields -1) while the link is down
+        let speed_mbps = read_trimmed(&path.join("speed")).and_then(|s| s.parse::<u32>().ok());
+
+        let pci_address = topology::pci_device_dir(&path.join("device"))
+            .and_then(|dir| dir.file_name().map(|n| n.to_string_lossy().to_string()));
+
+        interfaces.push(NetworkInterface {
+            name: entry.file_name().to_string_lossy().to_string(),
+            kind,
+            mac_address: read_trimmed(&path.join("address")),
+            mtu: read_trimmed(&path.join("mtu")).and_then(|m| m.parse::<u32>().ok()),
+            operstate: read_trimmed(&path.join("operstate")),
+            speed_mbps,
+            driver: link_name(&path.join("device/driver")),
+            pci_address,
+            bond_members,
+        });
+    }
+
+    interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(interfaces)
+}
+
+fn list_rdma_devices(ib_root: &Path) -> std::io::Result<Vec<RdmaDevice>> {
+    let mut devices = Vec::new();
+
+    for entry in fs::read_dir(ib_root)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        let mut ports = Vec::new();
+        if let Ok(port_entries) = fs::read_dir(path.join("ports")) {
+            for port_entry in port_entries.flatten() {
+                let Ok(port) = port_entry.file_name().to_string_lossy().parse::<u32>() else {
+                    continue;
+                };
+                let port_path = port_entry.path();
+                let rate = read_trimmed(&port_path.join("rate"));
+
+                ports.push(RdmaPort {
+                    port,
+                    state: read_trimmed(&port_path.join("state")).map(|s| strip_state_code(&s)),
+                    physical_state: read_trimmed(&port_path.join("phys_state"))
+                        .map(|s| strip_state_code(&s)),
+                    rate_gbps: rate.as_deref().and_then(parse_rate_gbps),
+                    rate,
+                    link_layer: read_trimmed(&port_path.join("link_layer")),
+                });
+            }
+        }
+        ports.sort_by_key(|port| port.port);
+
+        let netdevs = fs::read_dir(path.join("device/net"))
+            .map(|entries| {
+                let mut netdevs: Vec<String> = entries
+                    .flatten()
+                    .map(|e| e.file_name().to_string_lossy().to_string())
+                    .collect();
+                netdevs.sort();
+                netdevs
+            })
+            .unwrap_or_default();
+
+        devices.push(RdmaDevice {
+            name: entry.file_name().to_string_lossy().to_string(),
+            node_guid: read_trimmed(&path.join("node_guid")),
+            firmware_version: read_trimmed(&path.join("fw_ver")),
+            pci_address: topology::pci_device_dir(&path.join("device"))
+                .and_then(|dir| dir.file_name().map(|n| n.to_string_lossy().to_string())),
+            netdevs,
+            ports,
+        });
+    }
+
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(devices)
+}
+
+/// Port states are reported as `4: ACTIVE`; only the name is kept.
+fn strip_state_code(state: &str) -> String {
+    state
+        .split_once(':')
+        .map_or(state, |(_, name)| name)
+        .trim()
+        .to_string()
+}
+
+/// Parses port rates such as `200 Gb/sec (4X HDR)` or `2.5 Gb/sec (1X SDR)`.
+fn parse_rate_gbps(rate: &str) -> Option<f32> {
+    rate.split_whitespace().next()?.parse::<f32>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_port_attributes() {
+        assert_eq!(strip_state_code("4: ACTIVE"), "ACTIVE");
+        assert_eq!(strip_state_code("5: LinkUp"), "LinkUp");
+        assert_eq!(parse_rate_gbps("200 Gb/sec (4X HDR)"), Some(200.0));
+        assert_eq!(parse_rate_gbps("2.5 Gb/sec (1X SDR)"), Some(2.5));
+    }
+
+    #[test]
+    fn test_collect_network_from_sysfs() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let class = temp_dir.path();
+        let write = |path: &str, content: &str| {
+            let path = class.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+
+        let pci_device = temp_dir.path().join("devices/pci0000:00/0000:17:00.0");
+        fs::create_dir_all(pci_device.join("net")).unwrap();
+        fs::create_dir_all(pci_device.join("net/ens1f0np0")).unwrap();
+        let driver = temp_dir.path().join("drivers/mlx5_core");
+        fs::create_dir_all(&driver).unwrap();
+        std::os::unix::fs::symlink(&driver, pci_device.join("driver")).unwrap();
+
+        write("net/ens1f0np0/type", "1\n");
+        write("net/ens1f0np0/address", "b8:3f:d2:00:00:01\n");
+        write("net/ens1f0np0/mtu", "9000\n");
+        write("net/ens1f0np0/operstate", "up\n");
+        write("net/ens1f0np0/speed", "100000\n");
+        std::os::unix::fs::symlink(&pci_device, class.join("net/ens1f0np0/device")).unwrap();
+
+        write("net/bond0/type", "1\n");
+        write("net/bond0/operstate", "down\n");
+        write("net/bond0/bonding/slaves", "ens1f0np0 ens1f1np1\n");
+
+        write("net/docker0/type", "1\n");
+
+        write("infiniband/mlx5_0/fw_ver", "28.39.1002\n");
+        write("infiniband/mlx5_0/ports/1/rate", "100 Gb/sec (4X EDR)\n");
+        write("infiniband/mlx5_0/ports/1/state", "4: ACTIVE\n");
+        write("infiniband/mlx5_0/ports/1/phys_state", "5: LinkUp\n");
+        write("infiniband/mlx5_0/ports/1/link_layer", "Ethernet\n");
+        std::os::unix::fs::symlink(&pci_device, class.join("infiniband/mlx5_0/device")).unwrap();
+
+        let network = collect_network_from(class);
+
+        assert_eq!(network.interfaces.len(), 2);
+        let bond = &network.interfaces[0];
+        assert_eq!(bond.kind, InterfaceKind::Bond);
+        assert_eq!(bond.bond_members, vec!["ens1f0np0", "ens1f1np1"]);
+        assert_eq!(bond.speed_mbps, None);
+
+        let nic = &network.interfaces[1];
+        assert_eq!(nic.kind, InterfaceKind::Ethernet);
+        assert_eq!(nic.mtu, Some(9000));
+        assert_eq!(nic.speed_mbps, Some(100000));
+        assert_eq!(nic.driver.as_deref(), Some("mlx5_core"));
+        assert_eq!(nic.pci_address.as_deref(), Some("0000:17:00.0"));
+
+        let rdma = &network.rdma_devices[0];
+        assert_eq!(rdma.firmware_version.as_deref(), Some("28.39.1002"));
+        assert_eq!(rdma.netdevs, vec!["ens1f0np0"]);
+        assert_eq!(rdma.ports[0].state.as_deref(), Some("ACTIVE"));
+        assert_eq!(rdma.ports[0].rate_gbps, Some(100.0));
+    }
+}
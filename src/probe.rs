@@ -0,0 +1,218 @@
+use crate::config::ProbeSettings;
+use log::{info, warn};
+use serde::Serialize;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use which::which_global;
+
+/// How long to wait for the output readers once the process is gone. A
+/// daemonized grandchild may keep the pipes open forever.
+const READER_GRACE_PERIOD: Duration = Duration::from_millis(500);
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeStatus {
+    Ok,
+    NotFound,
+    Failed,
+    TimedOut,
+}
+
+#[derive(Debug)]
+pub(crate) struct ProbeOutput {
+    pub status: ProbeStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ProbeOutput {
+    fn without_output(status: ProbeStatus) -> Self {
+        ProbeOutput {
+            status,
+            stdout: String::new(),
+            stderr: String::new(),
+        }
+    }
+}
+
+/// Runs an external probe command looked up on `PATH`. The command gets no
+/// stdin, runs in its own process group with the C locale, is killed together
+/// with its children once the timeout expires, and only the first
+/// `max_output_bytes` of stdout and stderr are kept.
+pub(crate) fn run(bin: &str, args: &[&str], settings: &ProbeSettings) -> ProbeOutput {
+    let Ok(path) = which_global(bin) else {
+        return ProbeOutput::without_output(ProbeStatus::NotFound);
+    };
+
+    let mut child = match Command::new(&path)
+        .args(args)
+        .env("LC_ALL", "C")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed starting {}: {e}", path.display());
+            return ProbeOutput::without_output(ProbeStatus::Failed);
+        }
+    };
+
+    let stdout = spawn_reader(child.stdout.take(), settings.max_output_bytes);
+    let stderr = spawn_reader(child.stderr.take(), settings.max_output_bytes);
+
+    let timeout = Duration::from_secs(settings.timeout_secs);
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(exit_status)) if exit_status.success() => break ProbeStatus::Ok,
+            Ok(Some(exit_status)) => {
+                info!("{bin} {} exited with {exit_status}", args.join(" "));
+                break ProbeStatus::Failed;
+            }
+            Ok(None) if started.elapsed() >= timeout => {
+                warn!(
+                    "{bin} {} timed out after {} seconds, killing it",
+                    args.join(" "),
+                    settings.timeout_secs
+                );
+                kill_process_group(child.id());
+                let _ = child.wait();
+                break ProbeStatus::TimedOut;
+            }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => {
+                warn!("Failed waiting for {bin}: {e}");
+                kill_process_group(child.id());
+                let _ = child.wait();
+                break ProbeStatus::Failed;
+            }
+        }
+    };
+
+    ProbeOutput {
+        status,
+        stdout: stdout.recv_timeout(READER_GRACE_PERIOD).unwrap_or_default(),
+        stderr: stderr.recv_timeout(READER_GRACE_PERIOD).unwrap_or_default(),
+    }
+}
+
+/// Stdout of a probe command that succeeded, `None` otherwise.
+pub(crate) fn output(bin: &str, args: &[&str], settings: &ProbeSettings) -> Option<String> {
+    let output = run(bin, args, settings);
+    (output.status == ProbeStatus::Ok).then_some(output.stdout)
+}
+
+fn spawn_reader<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    max_output_bytes: usize,
+) -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let Some(mut pipe) = pipe else {
+            let _ = tx.send(String::new());
+            return;
+        };
+
+        let mut buf = Vec::new();
+        let _ = pipe
+            .by_ref()
+            .take(max_output_bytes as u64)
+            .read_to_end(&mut buf);
+        // keep draining so the process does not block on a full pipe
+        let _ = std::io::copy(&mut pipe, &mut std::io::sink());
+        let _ = tx.send(String::from_utf8_lossy(&buf).to_string());
+    });
+    rx
+}
+
+fn kill_process_group(pid: u32) {
+    // the child was started with `process_group(0)`, so its pid is the pgid
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    fn fake_tool(dir: &Path, name: &str, script: &str) -> String {
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.display().to_string()
+    }
+
+    fn settings(timeout_secs: u64, max_output_bytes: usize) -> ProbeSettings {
+        ProbeSettings {
+            timeout_secs,
+            max_output_bytes,
+        }
+    }
+
+    #[test]
+    fn test_run_collects_output() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let tool = fake_tool(
+            temp_dir.path(),
+            "tool",
+            "echo \"version $1\"; echo warn >&2",
+        );
+
+        let output = run(&tool, &["1.2.3"], &settings(5, 1024));
+
+        assert_eq!(output.status, ProbeStatus::Ok);
+        assert_eq!(output.stdout, "version 1.2.3\n");
+        assert_eq!(output.stderr, "warn\n");
+    }
+
+    #[test]
+    fn test_run_kills_hung_tool() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let tool = fake_tool(temp_dir.path(), "hung", "echo started; sleep 30 & sleep 30");
+
+        let started = Instant::now();
+        let output = run(&tool, &[], &settings(1, 1024));
+
+        assert_eq!(output.status, ProbeStatus::TimedOut);
+        assert_eq!(output.stdout, "started\n");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_run_limits_output() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let tool = fake_tool(temp_dir.path(), "chatty", "yes | head -c 100000");
+
+        let output = run(&tool, &[], &settings(5, 16));
+
+        assert_eq!(output.status, ProbeStatus::Ok);
+        assert_eq!(output.stdout.len(), 16);
+    }
+
+    #[test]
+    fn test_run_reports_failures() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let tool = fake_tool(temp_dir.path(), "broken", "exit 3");
+
+        assert_eq!(
+            run(&tool, &[], &settings(5, 16)).status,
+            ProbeStatus::Failed
+        );
+        assert_eq!(
+            run("client-hw-info-missing-tool", &[], &settings(5, 16)).status,
+            ProbeStatus::NotFound
+        );
+    }
+}
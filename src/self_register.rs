@@ -1,9 +1,11 @@
+use crate::collector::Inventory;
 use crate::config;
-use crate::hardware::NodeHardware;
-use crate::software::NodeSoftware;
-use crate::system::NodeSystem;
+use crate::mtls::CertificateRequest;
+use crate::signing::SigningKey;
 use log::{error, info, warn};
+use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::{env, fs};
@@ -13,6 +15,8 @@ const SYSTEMD_SERVICE_TEMPLATE: &str = include_str!(concat!(
     "/assets/systemd/client-hw-info.service"
 ));
 
+const SYSTEMD_TIMER_PATH: &str = "/etc/systemd/system/client-hw-info.timer";
+
 const SYSTEMD_TIMER_TEMPLATE: &str = include_str!(concat!(
     env!("CARGO_MANIFEST_DIR"),
     "/assets/systemd/client-hw-info.timer"
@@ -21,27 +25,34 @@ const SYSTEMD_TIMER_TEMPLATE: &str = include_str!(concat!(
 #[derive(Serialize)]
 struct SelfRegisterRequest<'a> {
     register_token: &'a str,
-    hardware: &'a NodeHardware,
-    software: &'a NodeSoftware,
-    system: &'a NodeSystem,
+    #[serde(flatten)]
+    inventory: &'a Inventory,
+    labels: &'a BTreeMap<String, String>,
     username: &'a str,
     ssh_key_id: &'a str,
     hostname: &'a str,
     endpoint: &'a String,
     price_per_hour: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    csr: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signing_public_key: Option<String>,
 }
 #[derive(Deserialize, Debug)]
 pub(crate) struct SelfRegisterResponse {
     node_id: String,
     next_access_token: String,
+    /// Issued for the CSR of a node registering with mTLS.
+    #[serde(default)]
+    client_certificate: Option<String>,
 }
 
 pub(crate) struct SelfRegisterParams<'a> {
+    pub client: &'a Client,
     pub api_url: &'a str,
     pub register_token: &'a str,
-    pub node_hardware: &'a NodeHardware,
-    pub node_software: &'a NodeSoftware,
-    pub node_system: &'a NodeSystem,
+    pub inventory: &'a Inventory,
+    pub labels: &'a BTreeMap<String, String>,
     pub username: &'a str,
     pub ssh_key_id: &'a str,
     pub hostname: &'a str,
@@ -49,6 +60,11 @@ pub(crate) struct SelfRegisterParams<'a> {
     pub port: u16,
     pub price_per_hour: f64,
     pub skip_systemd: bool,
+    pub heartbeat_interval_minutes: u32,
+    /// Key and CSR of the client certificate requested with mTLS.
+    pub certificate_request: Option<&'a CertificateRequest>,
+    /// Key the node signs its heartbeats with.
+    pub signing_key: Option<&'a SigningKey>,
 }
 
 pub(crate) fn self_register(
@@ -63,8 +79,6 @@ fn self_register_with_config_path(
     self_register_params: &SelfRegisterParams<'_>,
     cfg_path: &PathBuf,
 ) -> Result<SelfRegisterResponse, Box<dyn std::error::Error>> {
-    let client = reqwest::blocking::Client::new();
-
     let final_endpoint = format!(
         "{}/node/self-register",
         self_register_params.api_url.trim_end_matches('/')
@@ -72,9 +86,8 @@ fn self_register_with_config_path(
 
     let payload = SelfRegisterRequest {
         register_token: self_register_params.register_token,
-        hardware: self_register_params.node_hardware,
-        software: self_register_params.node_software,
-        system: self_register_params.node_system,
+        inventory: self_register_params.inventory,
+        labels: self_register_params.labels,
         ssh_key_id: self_register_params.ssh_key_id,
         username: self_register_params.username,
         hostname: self_register_params.hostname,
@@ -83,9 +96,17 @@ fn self_register_with_config_path(
             self_register_params.ip_addr, self_register_params.port
         ),
         price_per_hour: self_register_params.price_per_hour,
+        csr: self_register_params
+            .certificate_request
+            .map(|request| request.csr_pem.as_str()),
+        signing_public_key: self_register_params.signing_key.map(SigningKey::public_key),
     };
     info!("Sending self-register request to {}", final_endpoint);
-    let resp = client.post(final_endpoint).json(&payload).send()?;
+    let resp = self_register_params
+        .client
+        .post(final_endpoint)
+        .json(&payload)
+        .send()?;
 
     if resp.status().is_success() {
         info!("Successfully sent self-register request. Parsing response.");
@@ -114,9 +135,22 @@ fn self_register_with_config_path(
             }
         }
 
+        if let Some(request) = self_register_params.certificate_request {
+            let certificate = parsed
+                .client_certificate
+                .as_deref()
+                .ok_or("server issued no client certificate for the CSR")?;
+            let dir = cfg_path.parent().ok_or("config file has no directory")?;
+            request.store_in(dir, certificate)?;
+        }
+
+        if let Some(key) = self_register_params.signing_key {
+            key.store()?;
+        }
+
         if !self_register_params.skip_systemd {
             create_systemd_service()?;
-            create_systemd_timer(15)?;
+            create_systemd_timer(self_register_params.heartbeat_interval_minutes)?;
             reload_and_enable_timer()?;
         }
 
@@ -140,7 +174,7 @@ fn create_systemd_service() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn create_systemd_timer(heartbeat_interval: u8) -> Result<(), Box<dyn std::error::Error>> {
+fn create_systemd_timer(heartbeat_interval: u32) -> Result<(), Box<dyn std::error::Error>> {
     info!("Creating systemd timer for node");
 
     let rendered = SYSTEMD_TIMER_TEMPLATE.replace(
@@ -148,9 +182,7 @@ fn create_systemd_timer(heartbeat_interval: u8) -> Result<(), Box<dyn std::error
         &heartbeat_interval.to_string(),
     );
 
-    let timer_path = Path::new("/etc/systemd/system/client-hw-info.timer");
-
-    fs::write(timer_path, rendered)?;
+    fs::write(SYSTEMD_TIMER_PATH, rendered)?;
 
     Ok(())
 }
@@ -173,38 +205,39 @@ fn reload_and_enable_timer() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use mockito::Server;
-
-    fn create_mock_hardware() -> NodeHardware {
-        NodeHardware {
-            gpu_count: 1,
-            gpu_vendor: String::from("Nvidia"),
-            gpu_type: String::from("AD102GL [L40]"),
-            gpu_memory: 48,
-            cpu_cores: 16,
-            memory_gb: 64,
-            storage_gb: 1024,
-        }
+/// Rewrites the timer created at registration with a new interval. Nodes
+/// registered with `--skip-systemd` have no timer and are left alone.
+pub(crate) fn reschedule_systemd_timer(
+    heartbeat_interval: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !Path::new(SYSTEMD_TIMER_PATH).exists() {
+        info!("No systemd timer installed, keeping the schedule");
+        return Ok(());
     }
 
-    fn create_mock_software() -> NodeSoftware {
-        NodeSoftware {
-            docker: String::from(""),
-            nvidia: String::from(""),
-            amd: String::from(""),
-        }
+    create_systemd_timer(heartbeat_interval)?;
+
+    let reload_status = Command::new("systemctl").arg("daemon-reload").status()?;
+    if !reload_status.success() {
+        return Err("systemctl daemon-reload failed".into());
     }
 
-    fn create_mock_system() -> NodeSystem {
-        NodeSystem {
-            os: String::from("Linux (Ubuntu 24.04)"),
-            kernel: String::from("Linux 6.11.0-26-generic"),
-        }
+    let restart_status = Command::new("systemctl")
+        .args(["restart", "client-hw-info.timer"])
+        .status()?;
+    if !restart_status.success() {
+        return Err("systemctl restart client-hw-info.timer failed".into());
     }
 
+    info!("Rescheduled the systemd timer to every {heartbeat_interval} minute(s)");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server};
+
     #[test]
     fn test_self_register_success() {
         let temp_dir = tempfile::tempdir().expect("temp dir should be created");
@@ -216,6 +249,9 @@ mod tests {
 
         let _mock = server
             .mock("POST", "/node/self-register")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "labels": { "region": "eu-central" }
+            })))
             .with_status(200)
             .with_header("content-type", "application/json")
             .with_body(
@@ -228,10 +264,9 @@ mod tests {
             )
             .create();
 
-        let hardware = create_mock_hardware();
-        let software = create_mock_software();
-        let system = create_mock_system();
+        let inventory = Inventory::mock();
 
+        let labels = BTreeMap::from([(String::from("region"), String::from("eu-central"))]);
         let username = "ubuntu";
         let register_token = "TOKEN_IN_USER_PROFILE";
         let private_key_id = "PRIVATE_KEY_TO_ACCESS_NODE";
@@ -241,12 +276,13 @@ mod tests {
         let price_per_hour = 1.25;
         let skip_systemd = true;
 
+        let client = Client::new();
         let self_register_params = SelfRegisterParams {
+            client: &client,
             api_url: &server.url(),
             register_token,
-            node_hardware: &hardware,
-            node_software: &software,
-            node_system: &system,
+            inventory: &inventory,
+            labels: &labels,
             username,
             ssh_key_id: private_key_id,
             hostname,
@@ -254,6 +290,9 @@ mod tests {
             port,
             price_per_hour,
             skip_systemd,
+            heartbeat_interval_minutes: 15,
+            certificate_request: None,
+            signing_key: None,
         };
 
         let result = self_register_with_config_path(&self_register_params, &cfg_path);
@@ -269,4 +308,69 @@ mod tests {
         assert_eq!(res_unwrap.node_id, "node-123");
         assert_eq!(res_unwrap.next_access_token, "token-abc");
     }
+
+    #[test]
+    fn test_self_register_with_certificate_request() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let cfg_path = temp_dir.path().join("config.env");
+        let inventory = Inventory::mock();
+        let labels = BTreeMap::new();
+        let request = CertificateRequest::generate("node-1").unwrap();
+
+        let mut server = Server::new();
+        let client = Client::new();
+        let self_register_params = SelfRegisterParams {
+            client: &client,
+            api_url: &server.url(),
+            register_token: "TOKEN_IN_USER_PROFILE",
+            inventory: &inventory,
+            labels: &labels,
+            username: "ubuntu",
+            ssh_key_id: "PRIVATE_KEY_TO_ACCESS_NODE",
+            hostname: "node-1",
+            ip_addr: "127.0.0.1",
+            port: 22,
+            price_per_hour: 1.25,
+            skip_systemd: true,
+            heartbeat_interval_minutes: 15,
+            certificate_request: Some(&request),
+            signing_key: None,
+        };
+
+        let issued = server
+            .mock("POST", "/node/self-register")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "csr": request.csr_pem
+            })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{
+                    "node_id": "node-123",
+                    "next_access_token": "token-abc",
+                    "client_certificate": "issued certificate"
+                }"#,
+            )
+            .create();
+        self_register_with_config_path(&self_register_params, &cfg_path)
+            .expect("self_register should succeed");
+        issued.assert();
+        issued.remove();
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("client.pem")).unwrap(),
+            "issued certificate"
+        );
+        assert!(temp_dir.path().join("client.key").exists());
+
+        // a server ignoring the CSR fails the registration
+        fs::remove_file(temp_dir.path().join("client.pem")).unwrap();
+        let _mock = server
+            .mock("POST", "/node/self-register")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"node_id": "node-123", "next_access_token": "token-abc"}"#)
+            .create();
+        assert!(self_register_with_config_path(&self_register_params, &cfg_path).is_err());
+        assert!(!temp_dir.path().join("client.pem").exists());
+    }
 }
@@ -0,0 +1,173 @@
+use crate::config;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use log::info;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use rustls::pki_types::PrivatePkcs8KeyDer;
+use rustls::pki_types::pem::PemObject;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const KEY_FILE: &str = "signing.key";
+
+pub(crate) const TIMESTAMP_HEADER: &str = "X-Exalsius-Timestamp";
+pub(crate) const NONCE_HEADER: &str = "X-Exalsius-Nonce";
+pub(crate) const SIGNATURE_HEADER: &str = "X-Exalsius-Signature";
+
+/// Ed25519 key of the node. Its public key is registered with the server,
+/// which verifies the signature of every heartbeat and rejects replays by
+/// timestamp and nonce.
+pub(crate) struct SigningKey {
+    pkcs8: Vec<u8>,
+    key_pair: Ed25519KeyPair,
+}
+
+/// Headers proving that a request body comes from this node.
+#[derive(Debug)]
+pub(crate) struct RequestSignature {
+    pub timestamp: String,
+    pub nonce: String,
+    pub signature: String,
+}
+
+impl SigningKey {
+    pub(crate) fn generate() -> Result<Self, Box<dyn std::error::Error>> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())?;
+        Self::from_pkcs8(pkcs8.as_ref())
+    }
+
+    fn from_pkcs8(pkcs8: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(SigningKey {
+            pkcs8: pkcs8.to_vec(),
+            key_pair: Ed25519KeyPair::from_pkcs8(pkcs8)?,
+        })
+    }
+
+    /// The stored key, `None` for nodes registered before heartbeats were
+    /// signed. A key that exists but cannot be read is an error, it must not
+    /// silently downgrade to unsigned heartbeats.
+    pub(crate) fn load() -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let path = config::config_dir_path()?.join(KEY_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let key = PrivatePkcs8KeyDer::from_pem_file(&path)
+            .map_err(|e| format!("failed reading the signing key {}: {e}", path.display()))?;
+        let key = Self::from_pkcs8(key.secret_pkcs8_der())
+            .map_err(|e| format!("invalid signing key {}: {e}", path.display()))?;
+        Ok(Some(key))
+    }
+
+    /// Stores the key next to the config file, readable only by the owner.
+    pub(crate) fn store(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let path = config::config_dir_path()?.join(KEY_FILE);
+        self.store_at(&path)?;
+        info!("Stored signing key in {}", path.display());
+        Ok(())
+    }
+
+    fn store_at(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        config::write_private_file(path, pem("PRIVATE KEY", &self.pkcs8).as_bytes())
+    }
+
+    /// Base64 of the raw 32-byte public key.
+    pub(crate) fn public_key(&self) -> String {
+        STANDARD.encode(self.key_pair.public_key().as_ref())
+    }
+
+    /// Signs `{timestamp}\n{nonce}\n{body}` with a fresh random nonce.
+    pub(crate) fn sign(&self, body: &[u8]) -> Result<RequestSignature, Box<dyn std::error::Error>> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_secs()
+            .to_string();
+        let mut nonce = [0; 16];
+        SystemRandom::new().fill(&mut nonce)?;
+        let nonce: String = nonce.iter().map(|byte| format!("{byte:02x}")).collect();
+
+        let signature = self
+            .key_pair
+            .sign(&signed_message(&timestamp, &nonce, body));
+        Ok(RequestSignature {
+            timestamp,
+            nonce,
+            signature: STANDARD.encode(signature.as_ref()),
+        })
+    }
+}
+
+fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {label}-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {label}-----\n"));
+    pem
+}
+
+fn signed_message(timestamp: &str, nonce: &str, body: &[u8]) -> Vec<u8> {
+    [timestamp.as_bytes(), b"\n", nonce.as_bytes(), b"\n", body].concat()
+}
+
+#[cfg(test)]
+pub(crate) fn verify(
+    public_key: &str,
+    timestamp: &str,
+    nonce: &str,
+    signature: &str,
+    body: &[u8],
+) -> bool {
+    use ring::signature::{ED25519, UnparsedPublicKey};
+
+    let (Ok(public_key), Ok(signature)) = (STANDARD.decode(public_key), STANDARD.decode(signature))
+    else {
+        return false;
+    };
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(&signed_message(timestamp, nonce, body), &signature)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_sign_and_store() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(KEY_FILE);
+        // a key left with a looser mode is tightened when it is replaced
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let key = SigningKey::generate().unwrap();
+        key.store_at(&path).unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        let stored = PrivatePkcs8KeyDer::from_pem_file(&path).unwrap();
+        let key = SigningKey::from_pkcs8(stored.secret_pkcs8_der()).unwrap();
+        let first = key.sign(b"{}").unwrap();
+        let second = key.sign(b"{}").unwrap();
+        assert_ne!(first.nonce, second.nonce);
+
+        let public_key = key.public_key();
+        let verifies = |signature: &RequestSignature, body: &[u8]| {
+            verify(
+                &public_key,
+                &signature.timestamp,
+                &signature.nonce,
+                &signature.signature,
+                body,
+            )
+        };
+        assert!(verifies(&first, b"{}"));
+        assert!(!verifies(&first, b"{\"gpu_count\":8}"));
+    }
+}
@@ -1,26 +1,84 @@
-use log::info;
-use serde::Serialize;
-use std::process::Command;
-use which::which_global;
+use crate::config::ProbeSettings;
+use crate::probe::{self, ProbeStatus};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
 
 #[derive(Serialize, Debug)]
 pub struct NodeSoftware {
     pub docker: String,
     pub nvidia: String,
     pub amd: String,
+    pub versions: SoftwareVersions,
+    /// Every installed tool from the probe list with its version.
+    pub tools: BTreeMap<String, ToolVersion>,
+    pub gpu_runtime: GpuRuntimeIntegration,
+    /// Outcome of every external tool invocation, keyed by the tool name.
+    pub probes: BTreeMap<String, ProbeStatus>,
 }
 
-fn get_version<P: AsRef<std::ffi::OsStr>>(bin: P, args: &[&str]) -> Option<String> {
-    let output = Command::new(bin).args(args).output().ok()?;
+#[derive(Serialize, Debug)]
+pub struct ToolVersion {
+    pub version: Option<String>,
+    pub raw: String,
+}
+
+/// Whether containers can actually get GPUs, not only whether the NVIDIA
+/// Container Toolkit is installed.
+#[derive(Serialize, Debug, Default)]
+pub struct GpuRuntimeIntegration {
+    pub docker_daemon_reachable: bool,
+    pub docker_runtimes: Vec<String>,
+    pub docker_default_runtime: Option<String>,
+    pub docker_nvidia_runtime: bool,
+    pub containerd_nvidia_runtime: bool,
+    pub cdi_specs: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DockerInfo {
+    #[serde(rename = "ServerVersion")]
+    server_version: Option<String>,
+    #[serde(rename = "Runtimes")]
+    runtimes: Option<BTreeMap<String, serde_json::Value>>,
+    #[serde(rename = "DefaultRuntime")]
+    default_runtime: Option<String>,
+    #[serde(rename = "ServerErrors")]
+    server_errors: Option<Vec<String>>,
+}
+
+/// Versions parsed from the raw tool output above, which is kept as is for
+/// debugging.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct SoftwareVersions {
+    pub docker_engine: Option<String>,
+    pub docker_build: Option<String>,
+    pub nvidia_driver: Option<String>,
+    pub nvml: Option<String>,
+    pub cuda: Option<String>,
+    pub rocm: Option<String>,
+    pub amd_smi_tool: Option<String>,
+    pub amd_smi_library: Option<String>,
+    pub amdgpu_driver: Option<String>,
+}
+
+fn get_version(
+    bin: &str,
+    args: &[&str],
+    settings: &ProbeSettings,
+    probes: &mut BTreeMap<String, ProbeStatus>,
+) -> Option<String> {
+    let output = probe::run(bin, args, settings);
+    probes.insert(bin.to_string(), output.status);
 
-    if !output.status.success() {
+    if output.status != ProbeStatus::Ok {
         return None;
     }
 
-    let mut s = String::new();
-    s.push_str(&String::from_utf8_lossy(&output.stdout));
+    let mut s = output.stdout;
     if s.trim().is_empty() {
-        s.push_str(&String::from_utf8_lossy(&output.stderr));
+        s.push_str(&output.stderr);
     }
 
     let s = s.trim();
@@ -31,40 +89,438 @@ fn get_version<P: AsRef<std::ffi::OsStr>>(bin: P, args: &[&str]) -> Option<Strin
     }
 }
 
-pub fn collect_software_info() -> NodeSoftware {
+/// An external tool whose presence and version is reported.
+struct ToolProbe {
+    name: &'static str,
+    args: &'static [&'static str],
+}
+
+const TOOL_PROBES: &[ToolProbe] = &[
+    ToolProbe {
+        name: "docker",
+        args: &["--version"],
+    },
+    ToolProbe {
+        name: "nvidia-smi",
+        args: &["--version"],
+    },
+    ToolProbe {
+        name: "amd-smi",
+        args: &["version"],
+    },
+    ToolProbe {
+        name: "podman",
+        args: &["--version"],
+    },
+    ToolProbe {
+        name: "containerd",
+        args: &["--version"],
+    },
+    ToolProbe {
+        name: "nerdctl",
+        args: &["--version"],
+    },
+    ToolProbe {
+        name: "kubelet",
+        args: &["--version"],
+    },
+    ToolProbe {
+        name: "k3s",
+        args: &["--version"],
+    },
+    ToolProbe {
+        name: "rke2",
+        args: &["--version"],
+    },
+    ToolProbe {
+        name: "nvidia-ctk",
+        args: &["--version"],
+    },
+    ToolProbe {
+        name: "nvidia-container-runtime",
+        args: &["--version"],
+    },
+    ToolProbe {
+        name: "nvidia-container-cli",
+        args: &["--version"],
+    },
+];
+
+/// Config files of container runtimes that reference the NVIDIA runtime once
+/// `nvidia-ctk runtime configure` ran (k3s and rke2 generate their own).
+const CONTAINERD_CONFIGS: &[&str] = &[
+    "/etc/containerd/config.toml",
+    "/var/lib/rancher/k3s/agent/etc/containerd/config.toml",
+    "/var/lib/rancher/rke2/agent/etc/containerd/config.toml",
+];
+
+const CDI_SPEC_DIRS: &[&str] = &["/etc/cdi", "/var/run/cdi"];
+
+pub fn collect_software_info(settings: &ProbeSettings) -> NodeSoftware {
     info!("Start collecting client software versions");
 
-    let docker_path = which_global("docker").ok();
-    let docker_infos = docker_path
-        .as_ref()
-        .and_then(|p| get_version(p, &["--version"]))
-        .unwrap_or_default();
+    let mut probes = BTreeMap::new();
+    let mut tools = BTreeMap::new();
+    let mut raw_outputs = BTreeMap::new();
+
+    for tool in TOOL_PROBES {
+        let Some(raw) = get_version(tool.name, tool.args, settings, &mut probes) else {
+            continue;
+        };
+        info!("{}: {:?}", tool.name, raw);
+
+        tools.insert(
+            tool.name.to_string(),
+            ToolVersion {
+                version: parse_first_version(&raw),
+                raw: raw.clone(),
+            },
+        );
+        raw_outputs.insert(tool.name, raw);
+    }
+
+    let docker_infos = raw_outputs.remove("docker").unwrap_or_default();
+    let nvidia_smi_infos = raw_outputs.remove("nvidia-smi").unwrap_or_default();
+    let amd_smi_infos = raw_outputs.remove("amd-smi").unwrap_or_default();
 
-    info!("docker: {:?}", docker_infos);
+    let mut versions = SoftwareVersions::default();
+    parse_docker_version(&docker_infos, &mut versions);
+    parse_nvidia_smi_version(&nvidia_smi_infos, &mut versions);
+    parse_amd_smi_version(&amd_smi_infos, &mut versions);
 
-    let nvidia_smi_path = which_global("nvidia-smi").ok();
-    let nvidia_smi_infos = nvidia_smi_path
-        .as_ref()
-        .and_then(|p| get_version(p, &["--version"]))
-        .unwrap_or_default();
+    // older drivers only print the CUDA version in the banner of plain `nvidia-smi`
+    if tools.contains_key("nvidia-smi")
+        && (versions.nvidia_driver.is_none() || versions.cuda.is_none())
+    {
+        let banner = probe::run("nvidia-smi", &[], settings);
+        probes.insert(String::from("nvidia-smi banner"), banner.status);
+        if banner.status == ProbeStatus::Ok {
+            parse_nvidia_smi_banner(&banner.stdout, &mut versions);
+        }
+    }
 
-    info!("nvidia-smi: {:?}", nvidia_smi_infos);
+    let mut gpu_runtime = GpuRuntimeIntegration::default();
+    if tools.contains_key("docker") {
+        let docker_info = probe::run("docker", &["info", "--format", "{{json .}}"], settings);
+        probes.insert(String::from("docker info"), docker_info.status);
+        if docker_info.status == ProbeStatus::Ok {
+            match parse_docker_info(&docker_info.stdout, &mut gpu_runtime) {
+                Ok(server_version) => {
+                    // the daemon version is more accurate than the one of the CLI
+                    if server_version.is_some() {
+                        versions.docker_engine = server_version;
+                    }
+                }
+                Err(e) => warn!("Failed parsing docker info: {e}"),
+            }
+        }
+    }
 
-    let amd_smi_path = which_global("amd-smi").ok();
-    let amd_smi_infos = amd_smi_path
-        .as_ref()
-        .and_then(|p| get_version(p, &["version"]))
-        .unwrap_or_default();
+    gpu_runtime.containerd_nvidia_runtime = CONTAINERD_CONFIGS.iter().any(|path| {
+        fs::read_to_string(path).is_ok_and(|config| config.contains("nvidia-container-runtime"))
+    });
+    gpu_runtime.cdi_specs = list_cdi_specs(CDI_SPEC_DIRS);
 
-    info!("amd-smi: {:?}", amd_smi_infos);
+    info!("Parsed software versions: {:?}", versions);
+    info!("GPU container runtime integration: {:?}", gpu_runtime);
 
     let software_info = NodeSoftware {
         docker: docker_infos,
         nvidia: nvidia_smi_infos,
         amd: amd_smi_infos,
+        versions,
+        tools,
+        gpu_runtime,
+        probes,
     };
 
     info!("Finished collecting client software versions");
 
     software_info
 }
+
+/// Finds the first version-like token such as `1.7.22` or `v1.30.4+k3s1`.
+fn parse_first_version(output: &str) -> Option<String> {
+    output.split_whitespace().find_map(|token| {
+        let token = token.trim_end_matches([',', ';']);
+        let version = token.strip_prefix('v').unwrap_or(token);
+        (version.starts_with(|c: char| c.is_ascii_digit()) && version.contains('.'))
+            .then(|| version.to_string())
+    })
+}
+
+/// Fills the docker part of the GPU runtime integration from the output of
+/// `docker info --format '{{json .}}'` and returns the engine version.
+fn parse_docker_info(
+    output: &str,
+    gpu_runtime: &mut GpuRuntimeIntegration,
+) -> Result<Option<String>, serde_json::Error> {
+    let info: DockerInfo = serde_json::from_str(output.trim())?;
+
+    let runtimes = info.runtimes.unwrap_or_default();
+    let server_version = info.server_version.filter(|v| !v.is_empty());
+
+    // the CLI still prints JSON if the daemon is unreachable, without a server version
+    gpu_runtime.docker_daemon_reachable =
+        server_version.is_some() && info.server_errors.unwrap_or_default().is_empty();
+    gpu_runtime.docker_runtimes = runtimes.keys().cloned().collect();
+    gpu_runtime.docker_nvidia_runtime = runtimes.contains_key("nvidia");
+    gpu_runtime.docker_default_runtime = info.default_runtime.filter(|r| !r.is_empty());
+
+    Ok(server_version)
+}
+
+fn list_cdi_specs(dirs: &[&str]) -> Vec<String> {
+    let mut specs: Vec<String> = dirs
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten())
+        .map(|entry| entry.path().display().to_string())
+        .filter(|path| path.ends_with(".yaml") || path.ends_with(".json"))
+        .collect();
+    specs.sort();
+    specs
+}
+
+fn known_value(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty() && value != "N/A").then(|| value.to_string())
+}
+
+/// Parses `docker --version`, e.g. `Docker version 27.3.1, build ce12230`.
+/// The CLI is released together with the engine, so its version is reported
+/// as the engine version.
+fn parse_docker_version(output: &str, versions: &mut SoftwareVersions) {
+    let Some(rest) = output.trim().strip_prefix("Docker version ") else {
+        return;
+    };
+    let (version, build) = match rest.split_once(", build ") {
+        Some((version, build)) => (version, Some(build)),
+        None => (rest, None),
+    };
+    versions.docker_engine = known_value(version);
+    versions.docker_build = build.and_then(known_value);
+}
+
+/// Parses `nvidia-smi --version`, `DRIVER version : 550.54.14` lines.
+fn parse_nvidia_smi_version(output: &str, versions: &mut SoftwareVersions) {
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once(" : ").or_else(|| line.split_once(':')) else {
+            continue;
+        };
+        match key.trim().to_lowercase().as_str() {
+            "driver version" => versions.nvidia_driver = known_value(value),
+            "nvml version" => versions.nvml = known_value(value),
+            "cuda version" => versions.cuda = known_value(value),
+            _ => {}
+        }
+    }
+}
+
+/// Parses the banner of plain `nvidia-smi`
+/// (`NVIDIA-SMI 470.82.01  Driver Version: 470.82.01  CUDA Version: 11.4`)
+/// into the versions `--version` did not report.
+fn parse_nvidia_smi_banner(output: &str, versions: &mut SoftwareVersions) {
+    for line in output.lines() {
+        if let Some((_, rest)) = line.split_once("Driver Version:")
+            && versions.nvidia_driver.is_none()
+        {
+            versions.nvidia_driver = rest.split_whitespace().next().and_then(known_value);
+        }
+        if let Some((_, rest)) = line.split_once("CUDA Version:")
+            && versions.cuda.is_none()
+        {
+            versions.cuda = rest.split_whitespace().next().and_then(known_value);
+        }
+    }
+}
+
+/// Parses `amd-smi version`, a single line of `key: value` pairs separated by
+/// `|`, e.g. `AMDSMI Tool: 24.6.2+2b02a07 | AMDSMI Library version: 24.6.2.0 |
+/// ROCm version: 6.2.0`. Newer releases append the amdgpu driver version.
+fn parse_amd_smi_version(output: &str, versions: &mut SoftwareVersions) {
+    for pair in output.split(['|', '\n']) {
+        let Some((key, value)) = pair.split_once(':') else {
+            continue;
+        };
+        match key.trim().to_lowercase().as_str() {
+            "amdsmi tool" => versions.amd_smi_tool = known_value(value),
+            "amdsmi library version" => versions.amd_smi_library = known_value(value),
+            "rocm version" => versions.rocm = known_value(value),
+            "amdgpu version" => versions.amdgpu_driver = known_value(value),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(parser: fn(&str, &mut SoftwareVersions), output: &str) -> SoftwareVersions {
+        let mut versions = SoftwareVersions::default();
+        parser(output, &mut versions);
+        versions
+    }
+
+    #[test]
+    fn test_parse_first_version() {
+        let samples = [
+            ("podman version 4.9.3", "4.9.3"),
+            (
+                "containerd containerd.io 1.7.22 7f7fdf5fed64eb6a7caf99b3e12efcf9d60e311c",
+                "1.7.22",
+            ),
+            (
+                "containerd github.com/containerd/containerd v1.6.12 a05d175400b1145e5e6a735a6710579d181e7fb0",
+                "1.6.12",
+            ),
+            ("nerdctl version 1.7.6", "1.7.6"),
+            ("Kubernetes v1.30.2", "1.30.2"),
+            (
+                "k3s version v1.30.4+k3s1 (98262b5d)\ngo version go1.22.5",
+                "1.30.4+k3s1",
+            ),
+            (
+                "rke2 version v1.29.8+rke2r1 (bb5a9a0d0e9b2f3b7c3c0f1b2c6c9e0f1a2b3c4d)\ngo version go1.22.5 X:boringcrypto",
+                "1.29.8+rke2r1",
+            ),
+            (
+                "NVIDIA Container Toolkit CLI version 1.16.2\ncommit: a5a5833c14a15fd9c86bcece85d5ec6621b65652",
+                "1.16.2",
+            ),
+            (
+                "cli-version: 1.17.3\nlib-version: 1.17.3\nbuild date: 2024-12-04T09:47+00:00",
+                "1.17.3",
+            ),
+        ];
+
+        for (output, expected) in samples {
+            assert_eq!(
+                parse_first_version(output).as_deref(),
+                Some(expected),
+                "{output}"
+            );
+        }
+        assert_eq!(parse_first_version("no version here"), None);
+    }
+
+    #[test]
+    fn test_parse_docker_info_with_nvidia_runtime() {
+        let output = r#"{"ID":"7TRN:IPZB","Containers":3,"Driver":"overlay2","ServerVersion":"27.3.1","Runtimes":{"io.containerd.runc.v2":{"path":"runc"},"nvidia":{"path":"nvidia-container-runtime"},"runc":{"path":"runc"}},"DefaultRuntime":"nvidia","CDISpecDirs":["/etc/cdi","/var/run/cdi"],"Warnings":null}"#;
+        let mut gpu_runtime = GpuRuntimeIntegration::default();
+
+        let server_version = parse_docker_info(output, &mut gpu_runtime).unwrap();
+
+        assert_eq!(server_version.as_deref(), Some("27.3.1"));
+        assert!(gpu_runtime.docker_daemon_reachable);
+        assert!(gpu_runtime.docker_nvidia_runtime);
+        assert_eq!(
+            gpu_runtime.docker_default_runtime.as_deref(),
+            Some("nvidia")
+        );
+        assert_eq!(
+            gpu_runtime.docker_runtimes,
+            vec!["io.containerd.runc.v2", "nvidia", "runc"]
+        );
+    }
+
+    #[test]
+    fn test_parse_docker_info_without_daemon() {
+        let output = r#"{"ID":"","Containers":0,"ServerVersion":"","Runtimes":null,"DefaultRuntime":"","ServerErrors":["Cannot connect to the Docker daemon at unix:///var/run/docker.sock. Is the docker daemon running?"]}"#;
+        let mut gpu_runtime = GpuRuntimeIntegration::default();
+
+        let server_version = parse_docker_info(output, &mut gpu_runtime).unwrap();
+
+        assert_eq!(server_version, None);
+        assert!(!gpu_runtime.docker_daemon_reachable);
+        assert!(!gpu_runtime.docker_nvidia_runtime);
+        assert_eq!(gpu_runtime.docker_default_runtime, None);
+    }
+
+    #[test]
+    fn test_parse_docker_version() {
+        let versions = parse(
+            parse_docker_version,
+            "Docker version 24.0.7, build afdd53b\n",
+        );
+        assert_eq!(versions.docker_engine.as_deref(), Some("24.0.7"));
+        assert_eq!(versions.docker_build.as_deref(), Some("afdd53b"));
+
+        let versions = parse(
+            parse_docker_version,
+            "Docker version 20.10.21+dfsg1, build baeda1f",
+        );
+        assert_eq!(versions.docker_engine.as_deref(), Some("20.10.21+dfsg1"));
+
+        assert_eq!(
+            parse(parse_docker_version, "podman version 4.9.3"),
+            SoftwareVersions::default()
+        );
+    }
+
+    #[test]
+    fn test_parse_nvidia_smi_version() {
+        let output = "NVIDIA-SMI version  : 550.54.14
+NVML version        : 550.54
+DRIVER version      : 550.54.14
+CUDA Version        : 12.4";
+        let versions = parse(parse_nvidia_smi_version, output);
+        assert_eq!(versions.nvidia_driver.as_deref(), Some("550.54.14"));
+        assert_eq!(versions.nvml.as_deref(), Some("550.54"));
+        assert_eq!(versions.cuda.as_deref(), Some("12.4"));
+
+        let output = "NVIDIA-SMI version  : 570.133.20
+NVML version        : 570.133
+DRIVER version      : 570.133.20
+CUDA Version        : 12.8";
+        let versions = parse(parse_nvidia_smi_version, output);
+        assert_eq!(versions.nvidia_driver.as_deref(), Some("570.133.20"));
+        assert_eq!(versions.cuda.as_deref(), Some("12.8"));
+    }
+
+    #[test]
+    fn test_parse_nvidia_smi_banner() {
+        let banner = "Mon Nov 13 10:15:42 2023
++-----------------------------------------------------------------------------+
+| NVIDIA-SMI 470.223.02   Driver Version: 470.223.02   CUDA Version: 11.4     |
+|-------------------------------+----------------------+----------------------+";
+        let versions = parse(parse_nvidia_smi_banner, banner);
+        assert_eq!(versions.nvidia_driver.as_deref(), Some("470.223.02"));
+        assert_eq!(versions.cuda.as_deref(), Some("11.4"));
+        assert_eq!(versions.nvml, None);
+
+        // the banner only fills in what `--version` left out
+        let mut versions = parse(
+            parse_nvidia_smi_version,
+            "NVIDIA-SMI version  : 470.223.02
+NVML version        : 470.223
+DRIVER version      : 470.223.01",
+        );
+        assert_eq!(versions.cuda, None);
+        parse_nvidia_smi_banner(banner, &mut versions);
+        assert_eq!(versions.nvidia_driver.as_deref(), Some("470.223.01"));
+        assert_eq!(versions.nvml.as_deref(), Some("470.223"));
+        assert_eq!(versions.cuda.as_deref(), Some("11.4"));
+    }
+
+    #[test]
+    fn test_parse_amd_smi_version() {
+        let versions = parse(
+            parse_amd_smi_version,
+            "AMDSMI Tool: 23.4.2+505b858 | AMDSMI Library version: 6.0.0.0 | ROCm version: 6.0.0",
+        );
+        assert_eq!(versions.amd_smi_tool.as_deref(), Some("23.4.2+505b858"));
+        assert_eq!(versions.amd_smi_library.as_deref(), Some("6.0.0.0"));
+        assert_eq!(versions.rocm.as_deref(), Some("6.0.0"));
+        assert_eq!(versions.amdgpu_driver, None);
+
+        let versions = parse(
+            parse_amd_smi_version,
+            "AMDSMI Tool: 25.3.0+ede62f2 | AMDSMI Library version: 25.3.0 | ROCm version: 6.4.0 | amdgpu version: 6.12.12 | amd_hsmp version: N/A",
+        );
+        assert_eq!(versions.amd_smi_library.as_deref(), Some("25.3.0"));
+        assert_eq!(versions.rocm.as_deref(), Some("6.4.0"));
+        assert_eq!(versions.amdgpu_driver.as_deref(), Some("6.12.12"));
+    }
+}
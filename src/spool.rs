@@ -0,0 +1,262 @@
+use crate::config::{self, SpoolSettings};
+use log::{info, warn};
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SPOOL_DIR: &str = "spool";
+const EVENT_SUFFIX: &str = "-event.json";
+const REGULAR_SUFFIX: &str = ".json";
+
+/// Heartbeats that could not be delivered, kept in the state directory and
+/// replayed oldest first once the API is reachable again. Each heartbeat is
+/// one file named after the time it was spooled.
+pub(crate) struct Spool {
+    dir: PathBuf,
+    settings: SpoolSettings,
+}
+
+struct SpoolEntry {
+    path: PathBuf,
+    spooled_at: Duration,
+    /// Carries a health problem, kernel event or inventory change.
+    event: bool,
+}
+
+pub(crate) struct SpoolStatus {
+    pub entries: usize,
+    pub events: usize,
+    pub bytes: u64,
+    pub oldest_age: Option<Duration>,
+}
+
+impl Spool {
+    pub(crate) fn open(settings: &SpoolSettings) -> Result<Self, Box<dyn std::error::Error>> {
+        Spool::at(config::state_dir_path()?.join(SPOOL_DIR), settings)
+    }
+
+    pub(crate) fn at(
+        dir: PathBuf,
+        settings: &SpoolSettings,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        fs::create_dir_all(&dir)?;
+        Ok(Spool {
+            dir,
+            settings: settings.clone(),
+        })
+    }
+
+    /// Queues a heartbeat. Events are the last to be dropped when the spool is
+    /// full. Returns whether the heartbeat is kept, it is not if the spool is
+    /// disabled or full of events.
+    pub(crate) fn push(
+        &self,
+        payload: &Value,
+        event: bool,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        if self.settings.max_entries == 0 {
+            info!("Spool is disabled, dropping the heartbeat");
+            return Ok(false);
+        }
+
+        let suffix = if event { EVENT_SUFFIX } else { REGULAR_SUFFIX };
+        let path = self.dir.join(format!("{:020}{suffix}", now().as_nanos()));
+        fs::write(&path, serde_json::to_vec(payload)?)?;
+        info!("Spooled heartbeat in {}", path.display());
+
+        self.prune()?;
+        Ok(path.exists())
+    }
+
+    /// Sends the spooled heartbeats oldest first and removes each one `send`
+    /// accepts. Stops at the first error and returns it.
+    pub(crate) fn replay(
+        &self,
+        mut send: impl FnMut(Value) -> Result<(), Box<dyn std::error::Error>>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        self.prune()?;
+
+        let entries = self.entries()?;
+        if !entries.is_empty() {
+            info!("Replaying {} spooled heartbeats", entries.len());
+        }
+
+        let mut replayed = 0;
+        for entry in entries {
+            let payload = fs::read(&entry.path)
+                .ok()
+                .and_then(|content| serde_json::from_slice::<Value>(&content).ok());
+            match payload {
+                Some(payload) => {
+                    send(payload)?;
+                    replayed += 1;
+                }
+                None => warn!(
+                    "Dropping unreadable spooled heartbeat {}",
+                    entry.path.display()
+                ),
+            }
+            fs::remove_file(&entry.path)?;
+        }
+        Ok(replayed)
+    }
+
+    pub(crate) fn clear(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let entries = self.entries()?;
+        for entry in &entries {
+            fs::remove_file(&entry.path)?;
+        }
+        Ok(entries.len())
+    }
+
+    pub(crate) fn status(&self) -> Result<SpoolStatus, Box<dyn std::error::Error>> {
+        let entries = self.entries()?;
+        let mut bytes = 0;
+        for entry in &entries {
+            bytes += fs::metadata(&entry.path)?.len();
+        }
+        Ok(SpoolStatus {
+            entries: entries.len(),
+            events: entries.iter().filter(|entry| entry.event).count(),
+            bytes,
+            oldest_age: entries
+                .first()
+                .map(|entry| now().saturating_sub(entry.spooled_at)),
+        })
+    }
+
+    /// Drops entries past the age limit, then the oldest entries beyond the
+    /// entry limit, regular heartbeats before events.
+    fn prune(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let max_age = Duration::from_secs(self.settings.max_age_hours * 3600);
+        let now = now();
+
+        let mut kept = Vec::new();
+        for entry in self.entries()? {
+            if now.saturating_sub(entry.spooled_at) > max_age {
+                warn!(
+                    "Dropping spooled heartbeat {} older than {} hours",
+                    entry.path.display(),
+                    self.settings.max_age_hours
+                );
+                fs::remove_file(&entry.path)?;
+            } else {
+                kept.push(entry);
+            }
+        }
+
+        let excess = kept.len().saturating_sub(self.settings.max_entries);
+        let dropped = kept
+            .iter()
+            .filter(|entry| !entry.event)
+            .chain(kept.iter().filter(|entry| entry.event))
+            .take(excess);
+        for entry in dropped {
+            warn!("Spool is full, dropping heartbeat {}", entry.path.display());
+            fs::remove_file(&entry.path)?;
+        }
+        Ok(())
+    }
+
+    /// Spooled heartbeats, oldest first.
+    fn entries(&self) -> Result<Vec<SpoolEntry>, Box<dyn std::error::Error>> {
+        let mut entries = Vec::new();
+        for dir_entry in fs::read_dir(&self.dir)? {
+            let path = dir_entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let (stem, event) = match name.strip_suffix(EVENT_SUFFIX) {
+                Some(stem) => (stem, true),
+                None => match name.strip_suffix(REGULAR_SUFFIX) {
+                    Some(stem) => (stem, false),
+                    None => continue,
+                },
+            };
+            let Ok(nanos) = stem.parse::<u64>() else {
+                continue;
+            };
+            entries.push(SpoolEntry {
+                path,
+                spooled_at: Duration::from_nanos(nanos),
+                event,
+            });
+        }
+        entries.sort_by_key(|entry| entry.spooled_at);
+        Ok(entries)
+    }
+}
+
+pub(crate) fn unix_time() -> u64 {
+    now().as_secs()
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spool(dir: &tempfile::TempDir, max_entries: usize) -> Spool {
+        let settings = SpoolSettings {
+            max_entries,
+            max_age_hours: 24,
+        };
+        Spool::at(dir.path().join(SPOOL_DIR), &settings).unwrap()
+    }
+
+    #[test]
+    fn test_spool_replays_in_order_and_keeps_events() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let spool = spool(&temp_dir, 2);
+
+        spool.push(&serde_json::json!({ "n": 1 }), true).unwrap();
+        spool.push(&serde_json::json!({ "n": 2 }), false).unwrap();
+        spool.push(&serde_json::json!({ "n": 3 }), false).unwrap();
+
+        let status = spool.status().unwrap();
+        assert_eq!(status.entries, 2);
+        assert_eq!(status.events, 1);
+
+        let mut sent = Vec::new();
+        let replayed = spool
+            .replay(|payload| {
+                sent.push(payload["n"].as_u64().unwrap());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(replayed, 2);
+        assert_eq!(sent, vec![1, 3]);
+        assert_eq!(spool.status().unwrap().entries, 0);
+    }
+
+    #[test]
+    fn test_spool_keeps_entries_after_failed_replay() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let spool = spool(&temp_dir, 10);
+
+        spool.push(&serde_json::json!({ "n": 1 }), false).unwrap();
+        spool.push(&serde_json::json!({ "n": 2 }), false).unwrap();
+        // an entry spooled two days ago is past the age limit
+        let stale = now().saturating_sub(Duration::from_secs(48 * 3600));
+        fs::write(
+            temp_dir
+                .path()
+                .join(SPOOL_DIR)
+                .join(format!("{:020}{EVENT_SUFFIX}", stale.as_nanos())),
+            "{}",
+        )
+        .unwrap();
+
+        assert!(spool.replay(|_| Err("unreachable".into())).is_err());
+        assert_eq!(spool.status().unwrap().entries, 2);
+
+        assert_eq!(spool.clear().unwrap(), 2);
+        assert_eq!(spool.status().unwrap().entries, 0);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::config::StorageSettings;
+use log::{info, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use sysinfo::Disks;
+
+#[derive(Serialize, Debug, Default)]
+pub struct NodeStorage {
+    pub disks: Vec<BlockDevice>,
+    pub filesystems: Vec<Filesystem>,
+    pub job_storage_bytes: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BlockDevice {
+    pub name: String,
+    pub model: Option<String>,
+    pub size_bytes: u64,
+    pub kind: DiskKind,
+    pub removable: bool,
+    pub serial_hash: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskKind {
+    Hdd,
+    Ssd,
+    Nvme,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Filesystem {
+    pub device: String,
+    pub mount_point: String,
+    pub file_system: String,
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+    pub job_storage: bool,
+}
+
+pub fn collect_storage(settings: &StorageSettings) -> NodeStorage {
+    info!("Start collecting storage information");
+
+    let disks = list_block_devices(Path::new("/sys/block")).unwrap_or_else(|e| {
+        warn!("Failed reading block devices: {e}");
+        Vec::new()
+    });
+    for disk in &disks {
+        info!(
+            "Disk {} ({:?}, {}) with {} GB",
+            disk.name,
+            disk.kind,
+            disk.model.as_deref().unwrap_or("unknown model"),
+            disk.size_bytes / (1000 * 1000 * 1000)
+        );
+    }
+
+    let filesystems: Vec<Filesystem> = Disks::new_with_refreshed_list()
+        .iter()
+        .map(|disk| {
+            let mount_point = disk.mount_point().to_string_lossy().to_string();
+            Filesystem {
+                device: disk.name().to_string_lossy().to_string(),
+                job_storage: is_job_storage(&mount_point, &settings.job_storage_mounts),
+                mount_point,
+                file_system: disk.file_system().to_string_lossy().to_string(),
+                total_bytes: disk.total_space(),
+                available_bytes: disk.available_space(),
+            }
+        })
+        .collect();
+
+    let job_storage_bytes = job_storage_bytes(&filesystems);
+    for fs in filesystems.iter().filter(|fs| fs.job_storage) {
+        info!(
+            "Job storage {} ({}) with {} of {} bytes available",
+            fs.mount_point, fs.file_system, fs.available_bytes, fs.total_bytes
+        );
+    }
+
+    info!("Finished collecting storage information");
+    NodeStorage {
+        disks,
+        filesystems,
+        job_storage_bytes,
+    }
+}
+
+fn is_job_storage(mount_point: &str, rules: &[String]) -> bool {
+    rules.iter().any(|rule| match rule.strip_suffix('*') {
+        Some(prefix) => mount_point.starts_with(prefix),
+        None => mount_point == rule,
+    })
+}
+
+/// Sums the job storage filesystems, counting a device mounted several times
+/// (e.g. bind mounts) only once.
+fn job_storage_bytes(filesystems: &[Filesystem]) -> u64 {
+    let mut seen = HashSet::new();
+    filesystems
+        .iter()
+        .filter(|fs| fs.job_storage && seen.insert(fs.device.as_str()))
+        .map(|fs| fs.total_bytes)
+        .sum()
+}
+
+fn list_block_devices(block_root: &Path) -> std::io::Result<Vec<BlockDevice>> {
+    let mut disks = Vec::new();
+
+    for entry in fs::read_dir(block_root)? {
+        let entry = entry?;
+        let path = entry.path();
+        // loop, ram, zram, device-mapper and md devices have no backing device
+        if !path.join("device").exists() {
+            continue;
+        }
+
+        let name = entry.file_name().to_string_lossy().to_string();
+        let read = |file: &str| {
+            fs::read_to_string(path.join(file))
+                .ok()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+
+        // the size is always reported in 512 byte sectors
+        let size_bytes = read("size")
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(0)
+            * 512;
+
+        let kind = if name.starts_with("nvme") {
+            DiskKind::Nvme
+        } else if read("queue/rotational").as_deref() == Some("1") {
+            DiskKind::Hdd
+        } else {
+            DiskKind::Ssd
+        };
+
+        let serial_hash = read("device/serial")
+            .or_else(|| read("device/wwid"))
+            .or_else(|| read("serial"))
+            .map(|serial| hash_serial(&serial));
+
+        disks.push(BlockDevice {
+            model: read("device/model"),
+            removable: read("removable").as_deref() == Some("1"),
+            name,
+            size_bytes,
+            kind,
+            serial_hash,
+        });
+    }
+
+    disks.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(disks)
+}
+
+/// Serial numbers are only sent hashed so disks can be told apart over time
+/// without disclosing the actual serial.
+fn hash_serial(serial: &str) -> String {
+    format!("{:x}", Sha256::digest(serial.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filesystem(
+        device: &str,
+        mount_point: &str,
+        total_bytes: u64,
+        job_storage: bool,
+    ) -> Filesystem {
+        Filesystem {
+            device: device.to_string(),
+            mount_point: mount_point.to_string(),
+            file_system: String::from("ext4"),
+            total_bytes,
+            available_bytes: total_bytes / 2,
+            job_storage,
+        }
+    }
+
+    #[test]
+    fn test_is_job_storage() {
+        let rules = vec![String::from("/"), String::from("/mnt/*")];
+
+        assert!(is_job_storage("/", &rules));
+        assert!(is_job_storage("/mnt/nvme0", &rules));
+        assert!(!is_job_storage("/boot", &rules));
+    }
+
+    #[test]
+    fn test_job_storage_bytes_counts_devices_once() {
+        let filesystems = vec![
+            filesystem("/dev/vda1", "/", 50, true),
+            filesystem("/dev/md0", "/mnt/scratch", 4000, true),
+            filesystem("/dev/md0", "/mnt/scratch-bind", 4000, true),
+            filesystem("/dev/vda2", "/boot", 1, false),
+        ];
+
+        assert_eq!(job_storage_bytes(&filesystems), 4050);
+    }
+
+    #[test]
+    fn test_list_block_devices() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = temp_dir.path();
+
+        for (name, rotational, size) in [("sda", "1", "7814037168"), ("nvme0n1", "0", "3907029168")]
+        {
+            fs::create_dir_all(root.join(name).join("device")).unwrap();
+            fs::create_dir_all(root.join(name).join("queue")).unwrap();
+            fs::write(root.join(name).join("queue/rotational"), rotational).unwrap();
+            fs::write(root.join(name).join("size"), size).unwrap();
+        }
+        fs::write(
+            root.join("nvme0n1/device/model"),
+            "Samsung SSD 990 PRO 2TB   \n",
+        )
+        .unwrap();
+        fs::write(root.join("nvme0n1/device/serial"), "S6Z2NJ0W123456\n").unwrap();
+        fs::create_dir_all(root.join("loop0")).unwrap();
+
+        let disks = list_block_devices(root).expect("block devices should be listed");
+
+        assert_eq!(disks.len(), 2);
+        assert_eq!(disks[0].name, "nvme0n1");
+        assert_eq!(disks[0].kind, DiskKind::Nvme);
+        assert_eq!(disks[0].model.as_deref(), Some("Samsung SSD 990 PRO 2TB"));
+        assert_eq!(disks[0].size_bytes, 3907029168 * 512);
+        assert_eq!(disks[0].serial_hash, Some(hash_serial("S6Z2NJ0W123456")));
+        assert_eq!(disks[1].kind, DiskKind::Hdd);
+        assert_eq!(disks[1].serial_hash, None);
+    }
+}
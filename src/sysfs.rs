@@ -0,0 +1,11 @@
+use std::fs;
+use std::path::Path;
+
+/// Reads a sysfs attribute without the trailing newline, `None` if it is
+/// missing or empty.
+pub(crate) fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
@@ -1,11 +1,42 @@
-use log::{error, info};
+use crate::dmi::{self, NodeDmi};
+use log::{error, info, warn};
 use serde::Serialize;
+use std::fs;
+use std::path::Path;
 use sysinfo::System;
 
 #[derive(Serialize, Debug)]
 pub struct NodeSystem {
     pub os: String,
     pub kernel: String,
+    pub os_release: OsRelease,
+    pub architecture: String,
+    pub kernel_release: String,
+    pub kernel_version: String,
+    pub hostname: Option<String>,
+    pub timezone: Option<String>,
+    pub boot_time: u64,
+    pub uptime_secs: u64,
+    pub virtualization: Virtualization,
+}
+
+/// The fields of `os-release(5)` that compatibility rules are based on.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct OsRelease {
+    pub id: Option<String>,
+    pub id_like: Vec<String>,
+    pub name: Option<String>,
+    pub pretty_name: Option<String>,
+    pub version_id: Option<String>,
+    pub version_codename: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Virtualization {
+    pub bare_metal: bool,
+    pub hypervisor: Option<String>,
+    pub container_runtime: Option<String>,
+    pub cloud_provider: Option<String>,
 }
 
 pub fn collect_system_info() -> NodeSystem {
@@ -23,8 +54,474 @@ pub fn collect_system_info() -> NodeSystem {
 
     let kernel = System::kernel_long_version();
     info!("Kernel Version: {}", kernel);
-    let system: NodeSystem = NodeSystem { os, kernel };
+
+    let os_release = fs::read_to_string("/etc/os-release")
+        .or_else(|_| fs::read_to_string("/usr/lib/os-release"))
+        .map(|content| parse_os_release(&content))
+        .unwrap_or_else(|e| {
+            warn!("Failed reading os-release: {e}");
+            OsRelease::default()
+        });
+    info!(
+        "Distribution {} {} ({})",
+        os_release.id.as_deref().unwrap_or("unknown"),
+        os_release.version_id.as_deref().unwrap_or("unknown"),
+        os_release
+            .version_codename
+            .as_deref()
+            .unwrap_or("no codename")
+    );
+
+    let kernel_release = read_proc_string("/proc/sys/kernel/osrelease")
+        .or_else(System::kernel_version)
+        .unwrap_or_default();
+    let kernel_version = read_proc_string("/proc/sys/kernel/version").unwrap_or_default();
+    let architecture = System::cpu_arch();
+    info!("Kernel release {kernel_release} on {architecture}");
+
+    let hostname = System::host_name();
+    let timezone = detect_timezone();
+    let boot_time = System::boot_time();
+    let uptime_secs = System::uptime();
+    info!(
+        "Host {} in timezone {} up for {} seconds",
+        hostname.as_deref().unwrap_or("unknown"),
+        timezone.as_deref().unwrap_or("unknown"),
+        uptime_secs
+    );
+
+    let virtualization = detect_virtualization();
+    info!(
+        "Virtualization: hypervisor {:?}, container {:?}, cloud {:?}",
+        virtualization.hypervisor, virtualization.container_runtime, virtualization.cloud_provider
+    );
+
+    let system: NodeSystem = NodeSystem {
+        os,
+        kernel,
+        os_release,
+        architecture,
+        kernel_release,
+        kernel_version,
+        hostname,
+        timezone,
+        boot_time,
+        uptime_secs,
+        virtualization,
+    };
 
     info!("Finished collecting system information");
     system
 }
+
+fn read_proc_string(path: &str) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Parses the `KEY=value` lines of `os-release(5)`. Values may be quoted with
+/// single or double quotes and use shell style backslash escapes.
+fn parse_os_release(content: &str) -> OsRelease {
+    let mut os_release = OsRelease::default();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = unquote_os_release_value(value.trim());
+        if value.is_empty() {
+            continue;
+        }
+
+        match key.trim() {
+            "ID" => os_release.id = Some(value),
+            "ID_LIKE" => {
+                os_release.id_like = value.split_whitespace().map(str::to_string).collect()
+            }
+            "NAME" => os_release.name = Some(value),
+            "PRETTY_NAME" => os_release.pretty_name = Some(value),
+            "VERSION_ID" => os_release.version_id = Some(value),
+            "VERSION_CODENAME" => os_release.version_codename = Some(value),
+            _ => {}
+        }
+    }
+
+    os_release
+}
+
+fn unquote_os_release_value(value: &str) -> String {
+    let quoted = value.len() >= 2
+        && ((value.starts_with('"') && value.ends_with('"'))
+            || (value.starts_with('\'') && value.ends_with('\'')));
+    if !quoted {
+        return value.to_string();
+    }
+
+    let mut unquoted = String::with_capacity(value.len());
+    let mut chars = value[1..value.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(escaped) = chars.next() {
+                unquoted.push(escaped);
+            }
+        } else {
+            unquoted.push(c);
+        }
+    }
+    unquoted
+}
+
+fn detect_timezone() -> Option<String> {
+    if let Some(tz) = std::env::var("TZ").ok().filter(|tz| !tz.is_empty()) {
+        return Some(tz.trim_start_matches(':').to_string());
+    }
+    if let Some(tz) = read_proc_string("/etc/timezone") {
+        return Some(tz);
+    }
+    fs::read_link("/etc/localtime")
+        .ok()
+        .and_then(|target| timezone_from_localtime_link(&target.to_string_lossy()))
+}
+
+/// `/etc/localtime` links into the zoneinfo database, e.g.
+/// `/usr/share/zoneinfo/Europe/Berlin`.
+fn timezone_from_localtime_link(target: &str) -> Option<String> {
+    target
+        .split_once("zoneinfo/")
+        .map(|(_, tz)| tz.trim_start_matches("posix/").to_string())
+        .filter(|tz| !tz.is_empty())
+}
+
+fn detect_virtualization() -> Virtualization {
+    let dmi = dmi::read_dmi_id(Path::new("/sys/class/dmi/id"));
+    let chassis_asset_tag = fs::read_to_string("/sys/class/dmi/id/chassis_asset_tag")
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default();
+
+    // KVM can hide the CPUID hypervisor leaf (e.g. `kvm=off` for GPU
+    // passthrough), so DMI strings are consulted as well
+    let hypervisor = cpuid_hypervisor()
+        .or_else(|| {
+            fs::read_to_string("/sys/hypervisor/type")
+                .ok()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        })
+        .or_else(|| dmi_hypervisor(&dmi));
+
+    let container_runtime = detect_container_runtime(
+        Path::new("/"),
+        std::env::var_os("KUBERNETES_SERVICE_HOST").is_some(),
+    );
+
+    Virtualization {
+        bare_metal: hypervisor.is_none() && container_runtime.is_none(),
+        hypervisor,
+        container_runtime,
+        cloud_provider: detect_cloud_provider(&dmi, &chassis_asset_tag),
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn cpuid_hypervisor() -> Option<String> {
+    use std::arch::x86_64::__cpuid;
+
+    // leaf 1, ECX bit 31 is reserved for hypervisors to announce themselves
+    if __cpuid(1).ecx & (1 << 31) == 0 {
+        return None;
+    }
+
+    let leaf = __cpuid(0x4000_0000);
+    let mut vendor = Vec::with_capacity(12);
+    for register in [leaf.ebx, leaf.ecx, leaf.edx] {
+        vendor.extend_from_slice(&register.to_le_bytes());
+    }
+    let vendor = String::from_utf8_lossy(&vendor)
+        .trim_matches(char::from(0))
+        .trim()
+        .to_string();
+
+    Some(hypervisor_from_cpuid_vendor(&vendor))
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn cpuid_hypervisor() -> Option<String> {
+    None
+}
+
+fn hypervisor_from_cpuid_vendor(vendor: &str) -> String {
+    match vendor {
+        "KVMKVMKVM" | "Linux KVM Hv" => String::from("kvm"),
+        "Microsoft Hv" => String::from("hyperv"),
+        "VMwareVMware" => String::from("vmware"),
+        "XenVMMXenVMM" => String::from("xen"),
+        "TCGTCGTCGTCG" => String::from("qemu"),
+        "VBoxVBoxVBox" => String::from("virtualbox"),
+        "ACRNACRNACRN" => String::from("acrn"),
+        "bhyve bhyve" => String::from("bhyve"),
+        "lrpepyh  vr" | "prl hyperv" => String::from("parallels"),
+        "" => String::from("unknown"),
+        other => other.to_lowercase(),
+    }
+}
+
+fn dmi_hypervisor(dmi: &NodeDmi) -> Option<String> {
+    let vendor = dmi.system_manufacturer.as_deref().unwrap_or_default();
+    let product = dmi.product_name.as_deref().unwrap_or_default();
+
+    let hypervisor = match (vendor, product) {
+        (_, p) if p.contains("KVM") => "kvm",
+        ("QEMU", _) => "qemu",
+        ("VMware, Inc.", _) => "vmware",
+        ("innotek GmbH", _) | (_, "VirtualBox") => "virtualbox",
+        ("Xen", _) => "xen",
+        ("Microsoft Corporation", "Virtual Machine") => "hyperv",
+        ("Parallels Software International Inc.", _) => "parallels",
+        // cloud VMs; their bare-metal offerings have product names ending in `.metal`
+        ("Amazon EC2", p) if !p.ends_with(".metal") => "kvm",
+        ("Google", "Google Compute Engine") => "kvm",
+        ("OpenStack Foundation", _) | (_, "OpenStack Nova") => "kvm",
+        _ => return None,
+    };
+    Some(hypervisor.to_string())
+}
+
+fn detect_container_runtime(root: &Path, kubernetes_env: bool) -> Option<String> {
+    if root.join(".dockerenv").exists() {
+        return Some(String::from("docker"));
+    }
+    if root.join("run/.containerenv").exists() {
+        return Some(String::from("podman"));
+    }
+
+    let cgroup = fs::read_to_string(root.join("proc/1/cgroup")).unwrap_or_default();
+    let mountinfo = fs::read_to_string(root.join("proc/self/mountinfo")).unwrap_or_default();
+    // only the root filesystem tells, a host running containers lists their
+    // mounts as well
+    let root_mount = root_mount(&mountinfo).unwrap_or_default();
+    let runtime = if kubernetes_env || cgroup.contains("kubepods") {
+        "kubernetes"
+    } else if cgroup.contains("/docker") || root_mount.contains("/docker/") {
+        "docker"
+    } else if cgroup.contains("libpod") {
+        "podman"
+    } else if cgroup.contains("/lxc") {
+        "lxc"
+    } else if cgroup.contains("containerd") || root_mount.contains("containerd") {
+        "containerd"
+    } else {
+        return None;
+    };
+    Some(runtime.to_string())
+}
+
+/// The mountinfo line of the filesystem mounted last on `/`.
+fn root_mount(mountinfo: &str) -> Option<&str> {
+    mountinfo
+        .lines()
+        .rfind(|line| line.split(' ').nth(4) == Some("/"))
+}
+
+fn detect_cloud_provider(dmi: &NodeDmi, chassis_asset_tag: &str) -> Option<String> {
+    let vendor = dmi.system_manufacturer.as_deref().unwrap_or_default();
+    let product = dmi.product_name.as_deref().unwrap_or_default();
+    let bios_vendor = dmi.bios_vendor.as_deref().unwrap_or_default();
+
+    let provider = if vendor == "Amazon EC2" || bios_vendor == "Amazon EC2" {
+        "aws"
+    } else if vendor == "Google" || product == "Google Compute Engine" {
+        "gcp"
+    } else if chassis_asset_tag == "7783-7084-3265-9085-8269-3286-77" {
+        // Azure marks its VMs with this fixed asset tag
+        "azure"
+    } else if chassis_asset_tag == "OracleCloud.com" {
+        "oci"
+    } else if vendor == "Alibaba Cloud" {
+        "alibaba"
+    } else if vendor == "Hetzner" {
+        "hetzner"
+    } else if vendor == "DigitalOcean" {
+        "digitalocean"
+    } else if vendor == "Scaleway" {
+        "scaleway"
+    } else if vendor == "OpenStack Foundation" || product == "OpenStack Nova" {
+        "openstack"
+    } else {
+        return None;
+    };
+    Some(provider.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dmi(vendor: &str, product: &str) -> NodeDmi {
+        NodeDmi {
+            system_manufacturer: Some(vendor.to_string()),
+            product_name: Some(product.to_string()),
+            ..NodeDmi::default()
+        }
+    }
+
+    #[test]
+    fn test_dmi_hypervisor() {
+        assert_eq!(
+            dmi_hypervisor(&dmi("QEMU", "Standard PC (Q35 + ICH9, 2009)")).as_deref(),
+            Some("qemu")
+        );
+        assert_eq!(
+            dmi_hypervisor(&dmi("Amazon EC2", "p4d.24xlarge")).as_deref(),
+            Some("kvm")
+        );
+        assert_eq!(dmi_hypervisor(&dmi("Amazon EC2", "p4d.metal")), None);
+        assert_eq!(dmi_hypervisor(&dmi("Supermicro", "SYS-421GE-TNRT")), None);
+    }
+
+    #[test]
+    fn test_detect_cloud_provider() {
+        assert_eq!(
+            detect_cloud_provider(&dmi("Google", "Google Compute Engine"), "").as_deref(),
+            Some("gcp")
+        );
+        assert_eq!(
+            detect_cloud_provider(
+                &dmi("Microsoft Corporation", "Virtual Machine"),
+                "7783-7084-3265-9085-8269-3286-77"
+            )
+            .as_deref(),
+            Some("azure")
+        );
+        assert_eq!(
+            detect_cloud_provider(&dmi("Dell Inc.", "PowerEdge XE9680"), ""),
+            None
+        );
+    }
+
+    #[test]
+    fn test_detect_container_runtime() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("proc/1")).unwrap();
+        fs::create_dir_all(root.join("proc/self")).unwrap();
+
+        // a Docker and containerd host lists the mounts of its containers
+        fs::write(root.join("proc/1/cgroup"), "0::/init.scope\n").unwrap();
+        fs::write(
+            root.join("proc/self/mountinfo"),
+            "26 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw\n\
+             612 26 0:63 / /var/lib/docker/containers/3f2a/mounts/shm rw shared:310 - tmpfs shm rw\n\
+             640 26 0:70 / /run/containerd/io.containerd.runtime.v2.task/k8s.io/9c1d/rootfs rw shared:320 - overlay overlay rw\n",
+        )
+        .unwrap();
+        assert_eq!(detect_container_runtime(root, false), None);
+        assert_eq!(
+            detect_container_runtime(root, true).as_deref(),
+            Some("kubernetes")
+        );
+
+        fs::write(
+            root.join("proc/1/cgroup"),
+            "12:pids:/system.slice/containerd.service/kubepods-burstable-pod1.slice\n",
+        )
+        .unwrap();
+        assert_eq!(
+            detect_container_runtime(root, false).as_deref(),
+            Some("kubernetes")
+        );
+
+        fs::write(root.join("proc/1/cgroup"), "0::/\n").unwrap();
+        fs::write(
+            root.join("proc/self/mountinfo"),
+            "530 480 0:52 / / rw,relatime master:1 - overlay overlay rw,lowerdir=/var/lib/docker/overlay2/l/ABC,upperdir=/var/lib/docker/overlay2/7e1/diff\n",
+        )
+        .unwrap();
+        assert_eq!(
+            detect_container_runtime(root, false).as_deref(),
+            Some("docker")
+        );
+
+        fs::create_dir_all(root.join("run")).unwrap();
+        fs::write(root.join("run/.containerenv"), "").unwrap();
+        assert_eq!(
+            detect_container_runtime(root, false).as_deref(),
+            Some("podman")
+        );
+    }
+
+    #[test]
+    fn test_parse_os_release_ubuntu() {
+        let content = r#"PRETTY_NAME="Ubuntu 24.04.1 LTS"
+NAME="Ubuntu"
+VERSION_ID="24.04"
+VERSION="24.04.1 LTS (Noble Numbat)"
+VERSION_CODENAME=noble
+ID=ubuntu
+ID_LIKE=debian
+HOME_URL="https://www.ubuntu.com/"
+UBUNTU_CODENAME=noble
+LOGO=ubuntu-logo
+"#;
+
+        assert_eq!(
+            parse_os_release(content),
+            OsRelease {
+                id: Some(String::from("ubuntu")),
+                id_like: vec![String::from("debian")],
+                name: Some(String::from("Ubuntu")),
+                pretty_name: Some(String::from("Ubuntu 24.04.1 LTS")),
+                version_id: Some(String::from("24.04")),
+                version_codename: Some(String::from("noble")),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_os_release_rocky() {
+        let content = r#"NAME="Rocky Linux"
+VERSION="9.4 (Blue Onyx)"
+ID="rocky"
+ID_LIKE="rhel centos fedora"
+VERSION_ID="9.4"
+PLATFORM_ID="platform:el9"
+PRETTY_NAME="Rocky Linux 9.4 (Blue Onyx)"
+ANSI_COLOR='0;32'
+# comment
+"#;
+
+        let os_release = parse_os_release(content);
+        assert_eq!(os_release.id.as_deref(), Some("rocky"));
+        assert_eq!(os_release.id_like, vec!["rhel", "centos", "fedora"]);
+        assert_eq!(os_release.version_id.as_deref(), Some("9.4"));
+        assert_eq!(os_release.version_codename, None);
+        assert_eq!(
+            unquote_os_release_value(r#""a \"quoted\" \$value""#),
+            r#"a "quoted" $value"#
+        );
+    }
+
+    #[test]
+    fn test_timezone_from_localtime_link() {
+        assert_eq!(
+            timezone_from_localtime_link("/usr/share/zoneinfo/Europe/Berlin").as_deref(),
+            Some("Europe/Berlin")
+        );
+        assert_eq!(
+            timezone_from_localtime_link("../usr/share/zoneinfo/posix/UTC").as_deref(),
+            Some("UTC")
+        );
+        assert_eq!(timezone_from_localtime_link("/etc/localtime.bak"), None);
+    }
+
+    #[test]
+    fn test_hypervisor_from_cpuid_vendor() {
+        assert_eq!(hypervisor_from_cpuid_vendor("KVMKVMKVM"), "kvm");
+        assert_eq!(hypervisor_from_cpuid_vendor("Microsoft Hv"), "hyperv");
+    }
+}
@@ -0,0 +1,259 @@
+use crate::sysfs::read_trimmed;
+use log::{info, warn};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Debug, Default)]
+pub struct NodeTopology {
+    pub numa_nodes: Vec<NumaNode>,
+    pub gpus: Vec<DeviceAffinity>,
+    pub nics: Vec<DeviceAffinity>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NumaNode {
+    pub id: u32,
+    pub cpulist: String,
+    pub cpu_count: usize,
+    pub memory_total_kb: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeviceAffinity {
+    pub name: String,
+    pub pci_address: Option<String>,
+    pub numa_node: Option<u32>,
+    pub local_cpulist: Option<String>,
+}
+
+/// Collects the NUMA layout and the NUMA/CPU affinity of the given GPUs
+/// (by PCI address) and of all network interfaces backed by a device.
+pub fn collect_topology(gpu_pci_addresses: &[String]) -> NodeTopology {
+    collect_topology_from(Path::new("/sys"), gpu_pci_addresses)
+}
+
+fn collect_topology_from(sys_root: &Path, gpu_pci_addresses: &[String]) -> NodeTopology {
+    info!("Start collecting NUMA topology");
+
+    let numa_nodes = list_numa_nodes(&sys_root.join("devices/system/node")).unwrap_or_else(|e| {
+        warn!("Failed reading NUMA nodes: {e}");
+        Vec::new()
+    });
+
+    let gpus = gpu_pci_addresses
+        .iter()
+        .map(|addr| {
+            let device_dir = sys_root.join("bus/pci/devices").join(addr);
+            read_device_affinity(addr.to_owned(), &device_dir)
+        })
+        .collect();
+
+    let nics = list_nic_affinities(&sys_root.join("class/net")).unwrap_or_else(|e| {
+        warn!("Failed reading network interfaces: {e}");
+        Vec::new()
+    });
+
+    for node in &numa_nodes {
+        info!(
+            "NUMA node {} with CPUs {} and {} kB memory",
+            node.id, node.cpulist, node.memory_total_kb
+        );
+    }
+
+    info!("Finished collecting NUMA topology");
+    NodeTopology {
+        numa_nodes,
+        gpus,
+        nics,
+    }
+}
+
+fn list_numa_nodes(node_root: &Path) -> std::io::Result<Vec<NumaNode>> {
+    let mut nodes = Vec::new();
+
+    for entry in fs::read_dir(node_root)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(id) = name
+            .strip_prefix("node")
+            .and_then(|id| id.parse::<u32>().ok())
+        else {
+            continue;
+        };
+
+        let cpulist = read_trimmed(&entry.path().join("cpulist")).unwrap_or_default();
+        let memory_total_kb = fs::read_to_string(entry.path().join("meminfo"))
+            .ok()
+            .and_then(|meminfo| parse_node_mem_total_kb(&meminfo))
+            .unwrap_or(0);
+
+        nodes.push(NumaNode {
+            id,
+            cpu_count: parse_cpulist(&cpulist).len(),
+            cpulist,
+            memory_total_kb,
+        });
+    }
+
+    nodes.sort_by_key(|node| node.id);
+    Ok(nodes)
+}
+
+fn list_nic_affinities(net_root: &Path) -> std::io::Result<Vec<DeviceAffinity>> {
+    let mut nics = Vec::new();
+
+    for entry in fs::read_dir(net_root)? {
+        let entry = entry?;
+        let device_link = entry.path().join("device");
+        // virtual interfaces (lo, bridges, veth, ...) have no backing device
+        if !device_link.exists() {
+            continue;
+        }
+
+        let name = entry.file_name().to_string_lossy().to_string();
+        let device_dir = pci_device_dir(&device_link).unwrap_or(device_link);
+        nics.push(read_device_affinity(name, &device_dir));
+    }
+
+    nics.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(nics)
+}
+
+fn read_device_affinity(name: String, device_dir: &Path) -> DeviceAffinity {
+    let pci_address = fs::canonicalize(device_dir)
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+        .filter(|n| is_pci_address(n));
+
+    // the kernel reports -1 if the platform has no NUMA information
+    let numa_node = read_trimmed(&device_dir.join("numa_node")).and_then(|n| n.parse::<u32>().ok());
+    let local_cpulist = read_trimmed(&device_dir.join("local_cpulist"));
+
+    DeviceAffinity {
+        name,
+        pci_address,
+        numa_node,
+        local_cpulist,
+    }
+}
+
+/// Resolves a device link to the PCI function it sits on. Some drivers (e.g.
+/// virtio) put an intermediate device between the interface and the PCI device.
+pub(crate) fn pci_device_dir(device_link: &Path) -> Option<PathBuf> {
+    let mut dir = fs::canonicalize(device_link).ok()?;
+    for _ in 0..4 {
+        if dir
+            .file_name()
+            .is_some_and(|n| is_pci_address(&n.to_string_lossy()))
+        {
+            return Some(dir);
+        }
+        dir = dir.parent()?.to_path_buf();
+    }
+    None
+}
+
+pub(crate) fn is_pci_address(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() == 12
+        && bytes[4] == b':'
+        && bytes[7] == b':'
+        && bytes[10] == b'.'
+        && name
+            .chars()
+            .enumerate()
+            .all(|(i, c)| matches!(i, 4 | 7 | 10) || c.is_ascii_hexdigit())
+}
+
+/// Parses a kernel cpulist such as `0-3,8,10-11` into the individual CPU ids.
+fn parse_cpulist(cpulist: &str) -> Vec<u32> {
+    let mut cpus = Vec::new();
+    for part in cpulist.trim().split(',').filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                if let (Ok(start), Ok(end)) = (start.parse::<u32>(), end.parse::<u32>()) {
+                    cpus.extend(start..=end);
+                }
+            }
+            None => {
+                if let Ok(cpu) = part.parse::<u32>() {
+                    cpus.push(cpu);
+                }
+            }
+        }
+    }
+    cpus
+}
+
+fn parse_node_mem_total_kb(meminfo: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find(|line| line.contains("MemTotal:"))
+        .and_then(|line| line.split_whitespace().rev().nth(1))
+        .and_then(|kb| kb.parse::<u64>().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpulist() {
+        assert_eq!(parse_cpulist("0-3,8,10-11\n"), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_cpulist("5"), vec![5]);
+        assert!(parse_cpulist("").is_empty());
+    }
+
+    #[test]
+    fn test_collect_topology_from_sysfs() {
+        let temp_dir = tempfile::tempdir().expect("temp dir should be created");
+        let sys = temp_dir.path();
+
+        for (id, cpulist, mem) in [(0, "0-7", 1024), (1, "8-15", 2048)] {
+            let node = sys.join(format!("devices/system/node/node{id}"));
+            fs::create_dir_all(&node).unwrap();
+            fs::write(node.join("cpulist"), format!("{cpulist}\n")).unwrap();
+            fs::write(
+                node.join("meminfo"),
+                format!("Node {id} MemTotal:       {mem} kB\nNode {id} MemFree: 1 kB\n"),
+            )
+            .unwrap();
+        }
+        fs::write(sys.join("devices/system/node/online"), "0-1\n").unwrap();
+
+        let gpu = sys.join("bus/pci/devices/0000:81:00.0");
+        fs::create_dir_all(&gpu).unwrap();
+        fs::write(gpu.join("numa_node"), "1\n").unwrap();
+        fs::write(gpu.join("local_cpulist"), "8-15\n").unwrap();
+
+        let nic_device = sys.join("devices/pci0000:00/0000:03:00.0");
+        fs::create_dir_all(&nic_device).unwrap();
+        fs::write(nic_device.join("numa_node"), "-1\n").unwrap();
+        let nic = sys.join("class/net/ens1");
+        fs::create_dir_all(&nic).unwrap();
+        std::os::unix::fs::symlink(&nic_device, nic.join("device")).unwrap();
+        fs::create_dir_all(sys.join("class/net/lo")).unwrap();
+
+        let topology = collect_topology_from(sys, &["0000:81:00.0".to_string()]);
+
+        assert_eq!(topology.numa_nodes.len(), 2);
+        assert_eq!(topology.numa_nodes[1].cpu_count, 8);
+        assert_eq!(topology.numa_nodes[1].memory_total_kb, 2048);
+
+        assert_eq!(
+            topology.gpus[0].pci_address.as_deref(),
+            Some("0000:81:00.0")
+        );
+        assert_eq!(topology.gpus[0].numa_node, Some(1));
+        assert_eq!(topology.gpus[0].local_cpulist.as_deref(), Some("8-15"));
+
+        assert_eq!(topology.nics.len(), 1);
+        assert_eq!(topology.nics[0].name, "ens1");
+        assert_eq!(
+            topology.nics[0].pci_address.as_deref(),
+            Some("0000:03:00.0")
+        );
+        assert_eq!(topology.nics[0].numa_node, None);
+    }
+}
@@ -13,3 +13,85 @@ fn no_error() {
         .assert()
         .stderr(contains("Finished collecting hardware information"));
 }
+
+#[test]
+fn hung_probe_times_out() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::{Duration, Instant};
+
+    let home = tempfile::tempdir().unwrap();
+    let config_dir = home.path().join(".config").join("exalsius");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("config.toml"),
+        "[probes]\ntimeout_secs = 1\n",
+    )
+    .unwrap();
+
+    let bin_dir = tempfile::tempdir().unwrap();
+    let fake_nvidia_smi = bin_dir.path().join("nvidia-smi");
+    std::fs::write(&fake_nvidia_smi, "#!/bin/sh\nsleep 60\n").unwrap();
+    std::fs::set_permissions(&fake_nvidia_smi, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = format!(
+        "{}:{}",
+        bin_dir.path().display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+
+    let started = Instant::now();
+    let mut cmd = Command::cargo_bin("client-hw-info").unwrap();
+    cmd.env("HOME", home.path())
+        .env("PATH", path)
+        .arg("--skip-heartbeat")
+        .assert()
+        .success()
+        .stderr(contains("nvidia-smi --version timed out after 1 seconds"));
+    assert!(started.elapsed() < Duration::from_secs(30));
+}
+
+#[test]
+fn health_fails_on_missing_gpus() {
+    let home = tempfile::tempdir().unwrap();
+    let config_dir = home.path().join(".config").join("exalsius");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("config.toml"),
+        "[health]\nexpected_gpu_count = 64\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("client-hw-info").unwrap();
+    cmd.env("HOME", home.path())
+        .env("XDG_STATE_HOME", home.path().join("state"))
+        .arg("health")
+        .assert()
+        .failure()
+        .stdout(contains("FAIL  gpu_count"))
+        .stdout(contains("expected 64"))
+        .stdout(contains("Overall: FAIL"));
+}
+
+#[test]
+fn spool_status_and_clear() {
+    let home = tempfile::tempdir().unwrap();
+    let spool_dir = home.path().join("state").join("exalsius").join("spool");
+    std::fs::create_dir_all(&spool_dir).unwrap();
+    std::fs::write(spool_dir.join("01700000000000000000-event.json"), "{}").unwrap();
+
+    let mut cmd = Command::cargo_bin("client-hw-info").unwrap();
+    cmd.env("HOME", home.path())
+        .env("XDG_STATE_HOME", home.path().join("state"))
+        .args(["spool", "clear"])
+        .assert()
+        .success()
+        .stdout(contains("Deleted 1 spooled heartbeats"));
+
+    let mut cmd = Command::cargo_bin("client-hw-info").unwrap();
+    cmd.env("HOME", home.path())
+        .env("XDG_STATE_HOME", home.path().join("state"))
+        .args(["spool", "status"])
+        .assert()
+        .success()
+        .stdout(contains("0 spooled heartbeats"));
+}